@@ -0,0 +1,268 @@
+// Copyright 2025 The Axvisor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Emulation of data cache maintenance by set/way.
+//!
+//! Set/way operations only affect the caches of the CPU executing them, and not the system
+//! caches, so a guest using them to clean its memory, e.g. before enabling its MMU, can not rely
+//! on them once it is migrated between CPUs or shares caches with other VMs. As KVM does, they
+//! are trapped with `HCR_EL2.TSW` and replaced with cleaning and invalidating all the guest
+//! memory by VA, on the first set/way operation and whenever the guest turns its caches on or off
+//! afterwards.
+
+#[cfg(target_arch = "aarch64")]
+use core::arch::asm;
+
+use axaddrspace::device::SysRegAddr;
+#[cfg(target_arch = "aarch64")]
+use axvisor_api::memory::{PhysAddr, phys_to_virt};
+
+/// `DC ISW`, op0 = 1, op1 = 0, CRn = 7, CRm = 6, op2 = 2.
+const SYSREG_DC_ISW: usize = 0x14_1c0c;
+/// `DC CSW`, op0 = 1, op1 = 0, CRn = 7, CRm = 10, op2 = 2.
+const SYSREG_DC_CSW: usize = 0x14_1c14;
+/// `DC CISW`, op0 = 1, op1 = 0, CRn = 7, CRm = 14, op2 = 2.
+const SYSREG_DC_CISW: usize = 0x14_1c1c;
+/// AArch32 `DCISW`, `DCCSW` and `DCCISW`, the same as above on CP15, i.e., with op0 = 0.
+const SYSREG_CP15_DCISW: usize = 0x04_1c0c;
+const SYSREG_CP15_DCCSW: usize = 0x04_1c14;
+const SYSREG_CP15_DCCISW: usize = 0x04_1c1c;
+
+/// Stage 2 descriptor bits.
+const S2_DESC_VALID: u64 = 1 << 0;
+const S2_DESC_TABLE: u64 = 1 << 1;
+/// The output address of a stage 2 descriptor, bits \[47:12\].
+const S2_DESC_ADDR_MASK: u64 = 0x0000_ffff_ffff_f000;
+/// `MemAttr[3:2]` of a stage 2 block or page descriptor, bits \[5:4\], `0b00` for Device memory.
+const S2_DESC_MEMATTR_OUTER_MASK: u64 = 0b11 << 4;
+
+/// Returns whether the trapped system instruction `addr` is a data cache maintenance by set/way.
+pub fn is_dc_set_way(addr: SysRegAddr) -> bool {
+    matches!(
+        addr.addr(),
+        SYSREG_DC_ISW
+            | SYSREG_DC_CSW
+            | SYSREG_DC_CISW
+            | SYSREG_CP15_DCISW
+            | SYSREG_CP15_DCCSW
+            | SYSREG_CP15_DCCISW
+    )
+}
+
+/// The tracking of the guest caches after set/way operations.
+///
+/// The guest memory is flushed on the first set/way operation, and its caches are then tracked
+/// with trapped writes to the VM control registers: the memory is flushed again whenever the
+/// caches are turned on or off, and the tracking stops once they are on.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct SetWayTracker {
+    tracking: bool,
+}
+
+impl SetWayTracker {
+    /// Returns whether the guest caches are tracked, i.e. whether writes to the VM control
+    /// registers must be trapped.
+    pub const fn is_tracking(&self) -> bool {
+        self.tracking
+    }
+
+    /// Records a set/way operation of the guest.
+    ///
+    /// Returns whether the guest memory must be flushed, only on the first operation, which
+    /// typically comes in a loop over all the sets and ways.
+    pub fn set_way(&mut self) -> bool {
+        let first = !self.tracking;
+        self.tracking = true;
+        first
+    }
+
+    /// Records a write to a VM control register of the guest, with its caches enabled before
+    /// (`were_enabled`) and after (`enabled`) it.
+    ///
+    /// Returns whether the guest memory must be flushed.
+    pub fn caches_updated(&mut self, were_enabled: bool, enabled: bool) -> bool {
+        if !self.tracking {
+            return false;
+        }
+        // Once the caches are on, the guest no longer needs set/way operations to make its
+        // memory coherent, stop tracking until the next one.
+        if enabled {
+            self.tracking = false;
+        }
+        // Lines allocated while the caches were on may be stale once they are off, and the
+        // other way around, so the guest memory is flushed on every toggle.
+        were_enabled != enabled
+    }
+}
+
+/// Cleans and invalidates the data cache lines of `[start, start + size)` by VA to the point of
+/// coherency.
+#[cfg(target_arch = "aarch64")]
+fn flush_dcache_range(start: usize, size: usize) {
+    let ctr_el0: u64;
+    unsafe { asm!("mrs {0}, CTR_EL0", out(reg) ctr_el0) };
+    // CTR_EL0.DminLine, log2 of the number of words in the smallest data cache line.
+    let line = 4 << ((ctr_el0 >> 16) & 0xf);
+
+    let mut addr = start & !(line - 1);
+    while addr < start + size {
+        unsafe { asm!("dc civac, {0}", in(reg) addr) };
+        addr += line;
+    }
+}
+
+/// Returns whether the stage 2 block or page descriptor `desc` maps Normal memory, i.e. RAM.
+///
+/// Device memory, e.g. MMIO regions passed through to the guest, is neither cached nor safe to
+/// access with cache maintenance instructions, so it is skipped.
+const fn s2_desc_is_normal(desc: u64) -> bool {
+    desc & S2_DESC_MEMATTR_OUTER_MASK != 0
+}
+
+/// Returns the size of the memory mapped by a descriptor at `level` of a 4 KiB granule table.
+const fn level_size(level: u64) -> usize {
+    1 << (12 + 9 * (3 - level))
+}
+
+/// Cleans and invalidates all the Normal memory mapped by the stage 2 table at `table_pa`, which
+/// is at `level` and has `entries` descriptors.
+#[cfg(target_arch = "aarch64")]
+fn flush_stage2_table(table_pa: u64, level: u64, entries: usize) {
+    let table = phys_to_virt(PhysAddr::from_usize(table_pa as usize)).as_ptr_of::<u64>();
+    for i in 0..entries {
+        let desc = unsafe { table.add(i).read_volatile() };
+        if desc & S2_DESC_VALID == 0 {
+            continue;
+        }
+
+        let pa = desc & S2_DESC_ADDR_MASK;
+        match (level, desc & S2_DESC_TABLE != 0) {
+            (0..=2, true) => flush_stage2_table(pa, level + 1, 512),
+            // Level 3 descriptors must have bit 1 set to be pages.
+            (3, false) => {}
+            _ if !s2_desc_is_normal(desc) => {}
+            _ => {
+                let va = phys_to_virt(PhysAddr::from_usize(pa as usize));
+                flush_dcache_range(va.as_usize(), level_size(level));
+            }
+        }
+    }
+}
+
+/// Cleans and invalidates to the point of coherency all the guest RAM mapped by the stage 2
+/// translation table in `vttbr`, with the 4 KiB granule layout described by `vtcr`.
+#[cfg(target_arch = "aarch64")]
+pub fn flush_guest_memory(vttbr: u64, vtcr: u64) {
+    let t0sz = vtcr & 0x3f;
+    // VTCR_EL2.SL0 for the 4 KiB granule, 0 to 2 meaning starting at level 2 to 0.
+    let start_level = 2 - ((vtcr >> 6) & 0b11).min(2);
+    // The starting level may be made of up to 16 concatenated tables.
+    let entries = 1 << (64 - t0sz - (12 + 9 * (3 - start_level)));
+
+    flush_stage2_table(vttbr & S2_DESC_ADDR_MASK, start_level, entries);
+    unsafe { asm!("dsb sy") };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the `SysRegAddr` of the system instruction `op0, op1, CRn, CRm, op2`.
+    const fn sys(op0: usize, op1: usize, crn: usize, crm: usize, op2: usize) -> SysRegAddr {
+        SysRegAddr::new((op0 << 20) | (op2 << 17) | (op1 << 14) | (crn << 10) | (crm << 1))
+    }
+
+    #[test]
+    fn dc_set_way_encodings() {
+        // DC ISW, DC CSW and DC CISW.
+        assert!(is_dc_set_way(sys(1, 0, 7, 6, 2)));
+        assert!(is_dc_set_way(sys(1, 0, 7, 10, 2)));
+        assert!(is_dc_set_way(sys(1, 0, 7, 14, 2)));
+        // DCISW, DCCSW and DCCISW from AArch32, MCR p15, 0, <Rt>, c7, <CRm>, 2.
+        assert!(is_dc_set_way(sys(0, 0, 7, 6, 2)));
+        assert!(is_dc_set_way(sys(0, 0, 7, 10, 2)));
+        assert!(is_dc_set_way(sys(0, 0, 7, 14, 2)));
+    }
+
+    #[test]
+    fn dc_by_va_is_not_set_way() {
+        // DC IVAC, DC CVAC, DC CIVAC and DC ZVA.
+        assert!(!is_dc_set_way(sys(1, 0, 7, 6, 1)));
+        assert!(!is_dc_set_way(sys(1, 3, 7, 10, 1)));
+        assert!(!is_dc_set_way(sys(1, 3, 7, 14, 1)));
+        assert!(!is_dc_set_way(sys(1, 3, 7, 4, 1)));
+        // IC IALLU, and DCCISW from CP14 rather than CP15.
+        assert!(!is_dc_set_way(sys(1, 0, 7, 5, 0)));
+        assert!(!is_dc_set_way(sys(2, 0, 7, 14, 2)));
+    }
+
+    #[test]
+    fn stage2_memory_attributes() {
+        // MemAttr 0b1111, Normal Write-Back, and 0b0101, Normal Non-cacheable.
+        assert!(s2_desc_is_normal(0x4000_0000 | (0b1111 << 2) | 0b11));
+        assert!(s2_desc_is_normal(0x4000_0000 | (0b0101 << 2) | 0b11));
+        // MemAttr 0b0000 and 0b0001, Device-nGnRnE and Device-nGnRE.
+        assert!(!s2_desc_is_normal(0x0900_0000 | 0b11));
+        assert!(!s2_desc_is_normal(0x0900_0000 | (0b0001 << 2) | 0b11));
+    }
+
+    #[test]
+    fn set_way_flushes_once() {
+        let mut tracker = SetWayTracker::default();
+        assert!(!tracker.is_tracking());
+        assert!(tracker.set_way());
+        assert!(tracker.is_tracking());
+        assert!(!tracker.set_way());
+        assert!(tracker.is_tracking());
+    }
+
+    #[test]
+    fn untracked_caches_updates_do_not_flush() {
+        let mut tracker = SetWayTracker::default();
+        assert!(!tracker.caches_updated(false, true));
+        assert!(!tracker.caches_updated(true, false));
+        assert!(!tracker.is_tracking());
+    }
+
+    #[test]
+    fn tracking_until_caches_enabled() {
+        let mut tracker = SetWayTracker::default();
+        tracker.set_way();
+
+        // Writes not toggling the caches, e.g. to TTBR0_EL1, keep tracking without flushing.
+        assert!(!tracker.caches_updated(false, false));
+        assert!(tracker.is_tracking());
+
+        // Turning the caches on flushes and stops the tracking.
+        assert!(tracker.caches_updated(false, true));
+        assert!(!tracker.is_tracking());
+        assert!(!tracker.caches_updated(true, false));
+
+        // Until the next set/way operation.
+        assert!(tracker.set_way());
+        assert!(tracker.is_tracking());
+    }
+
+    #[test]
+    fn caches_turned_off_while_tracking() {
+        let mut tracker = SetWayTracker::default();
+        tracker.set_way();
+
+        // Turning the caches off flushes, and the tracking goes on.
+        assert!(tracker.caches_updated(true, false));
+        assert!(tracker.is_tracking());
+        assert!(tracker.caches_updated(false, true));
+        assert!(!tracker.is_tracking());
+    }
+}
@@ -15,6 +15,55 @@
 use core::{arch::asm, fmt::Formatter};
 
 use aarch64_cpu::registers::*;
+use axaddrspace::device::SysRegAddr;
+
+use crate::regs::VCpuReg;
+
+/// `SPSR.M[4]`, set if the exception was taken from AArch32 state.
+pub const SPSR_AARCH32_STATE: u64 = 1 << 4;
+/// `SPSR.T` for exceptions taken from AArch32 state, set in T32 state.
+pub const SPSR_AARCH32_T: u64 = 1 << 5;
+/// `SPSR.IT[7:0]` for exceptions taken from AArch32 state, split in `SPSR[15:10]` and `SPSR[26:25]`.
+pub const SPSR_AARCH32_IT_MASK: u64 = 0x0600_fc00;
+/// The AArch32 Supervisor mode in `SPSR.M[4:0]`.
+pub const SPSR_AARCH32_MODE_SVC: u64 = 0b10011;
+/// The AArch32 Abort mode in `SPSR.M[4:0]`.
+pub const SPSR_AARCH32_MODE_ABT: u64 = 0b10111;
+/// The AArch32 Undefined mode in `SPSR.M[4:0]`.
+pub const SPSR_AARCH32_MODE_UND: u64 = 0b11011;
+
+/// `HCR_EL2.VSE`, makes a virtual SError pending for EL1 and EL0, cleared when it is taken.
+pub const HCR_EL2_VSE: u64 = 1 << 8;
+
+// Virtual memory control registers trapped by `HCR_EL2.{TVM, TRVM}`, numbered as `SysRegAddr`,
+// i.e., `<op0><op2><op1><CRn>00000<CRm>0`, and with `op0` equal to 0 for AArch32 CP15 registers.
+const SYSREG_SCTLR_EL1: usize = 0x30_0400;
+const SYSREG_TTBR0_EL1: usize = 0x30_0800;
+const SYSREG_TTBR1_EL1: usize = 0x32_0800;
+const SYSREG_TCR_EL1: usize = 0x34_0800;
+const SYSREG_AFSR0_EL1: usize = 0x30_1402;
+const SYSREG_AFSR1_EL1: usize = 0x32_1402;
+const SYSREG_ESR_EL1: usize = 0x30_1404;
+const SYSREG_FAR_EL1: usize = 0x30_1800;
+const SYSREG_MAIR_EL1: usize = 0x30_2804;
+const SYSREG_AMAIR_EL1: usize = 0x30_2806;
+const SYSREG_CONTEXTIDR_EL1: usize = 0x32_3400;
+const SYSREG_CP15_SCTLR: usize = 0x00_0400;
+const SYSREG_CP15_TTBR0: usize = 0x00_0800;
+const SYSREG_CP15_TTBR1: usize = 0x02_0800;
+const SYSREG_CP15_TTBCR: usize = 0x04_0800;
+const SYSREG_CP15_DACR: usize = 0x00_0c00;
+const SYSREG_CP15_DFSR: usize = 0x00_1400;
+const SYSREG_CP15_IFSR: usize = 0x02_1400;
+const SYSREG_CP15_ADFSR: usize = 0x00_1402;
+const SYSREG_CP15_AIFSR: usize = 0x02_1402;
+const SYSREG_CP15_DFAR: usize = 0x00_1800;
+const SYSREG_CP15_IFAR: usize = 0x04_1800;
+const SYSREG_CP15_MAIR0: usize = 0x00_2804;
+const SYSREG_CP15_MAIR1: usize = 0x02_2804;
+const SYSREG_CP15_AMAIR0: usize = 0x00_2806;
+const SYSREG_CP15_AMAIR1: usize = 0x02_2806;
+const SYSREG_CP15_CONTEXTIDR: usize = 0x02_3400;
 
 /// A struct representing the AArch64 CPU context frame.
 ///
@@ -76,6 +125,11 @@ impl Default for Aarch64ContextFrame {
 }
 
 impl Aarch64ContextFrame {
+    /// Returns whether the context was running in AArch32 state, i.e. whether `SPSR.M[4]` is set.
+    pub fn is_aarch32(&self) -> bool {
+        self.spsr & SPSR_AARCH32_STATE != 0
+    }
+
     /// Returns the exception program counter (ELR).
     pub fn exception_pc(&self) -> usize {
         self.elr as usize
@@ -148,6 +202,306 @@ impl Aarch64ContextFrame {
     }
 }
 
+/// The floating-point and Advanced SIMD (FP/SIMD) register file of a PE.
+///
+/// This includes
+/// * the 32 128-bit SIMD&FP registers `V0`-`V31`,
+/// * the floating-point status register (FPSR),
+/// * the floating-point control register (FPCR).
+///
+/// The structure is aligned to 16 bytes so that the `V` registers can be stored and loaded
+/// with `stp`/`ldp` of `Q` registers.
+#[repr(C)]
+#[repr(align(16))]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct FpSimdRegisters {
+    /// The SIMD&FP registers `V0`-`V31`.
+    pub vregs: [u128; 32],
+    /// The floating-point status register.
+    pub fpsr: u64,
+    /// The floating-point control register.
+    pub fpcr: u64,
+}
+
+impl FpSimdRegisters {
+    /// Returns a zeroed FP/SIMD register file.
+    pub const fn new() -> Self {
+        Self {
+            vregs: [0; 32],
+            fpsr: 0,
+            fpcr: 0,
+        }
+    }
+
+    /// Stores the current FP/SIMD registers of the PE into this structure.
+    ///
+    /// # Safety
+    ///
+    /// FP/SIMD accesses must not be trapped at the current exception level,
+    /// i.e. `CPTR_EL2.TFP` must be clear.
+    pub(crate) unsafe fn store(&mut self) {
+        unsafe {
+            asm!(
+                ".arch_extension fp",
+                ".arch_extension simd",
+                "stp q0, q1, [{0}, #0 * 32]",
+                "stp q2, q3, [{0}, #1 * 32]",
+                "stp q4, q5, [{0}, #2 * 32]",
+                "stp q6, q7, [{0}, #3 * 32]",
+                "stp q8, q9, [{0}, #4 * 32]",
+                "stp q10, q11, [{0}, #5 * 32]",
+                "stp q12, q13, [{0}, #6 * 32]",
+                "stp q14, q15, [{0}, #7 * 32]",
+                "stp q16, q17, [{0}, #8 * 32]",
+                "stp q18, q19, [{0}, #9 * 32]",
+                "stp q20, q21, [{0}, #10 * 32]",
+                "stp q22, q23, [{0}, #11 * 32]",
+                "stp q24, q25, [{0}, #12 * 32]",
+                "stp q26, q27, [{0}, #13 * 32]",
+                "stp q28, q29, [{0}, #14 * 32]",
+                "stp q30, q31, [{0}, #15 * 32]",
+                in(reg) self.vregs.as_mut_ptr(),
+                options(nostack)
+            );
+            asm!(".arch_extension fp", "mrs {0}, FPSR", out(reg) self.fpsr);
+            asm!(".arch_extension fp", "mrs {0}, FPCR", out(reg) self.fpcr);
+        }
+    }
+
+    /// Restores the FP/SIMD registers of the PE from this structure.
+    ///
+    /// # Safety
+    ///
+    /// FP/SIMD accesses must not be trapped at the current exception level,
+    /// i.e. `CPTR_EL2.TFP` must be clear.
+    pub(crate) unsafe fn restore(&self) {
+        unsafe {
+            asm!(
+                ".arch_extension fp",
+                ".arch_extension simd",
+                "ldp q0, q1, [{0}, #0 * 32]",
+                "ldp q2, q3, [{0}, #1 * 32]",
+                "ldp q4, q5, [{0}, #2 * 32]",
+                "ldp q6, q7, [{0}, #3 * 32]",
+                "ldp q8, q9, [{0}, #4 * 32]",
+                "ldp q10, q11, [{0}, #5 * 32]",
+                "ldp q12, q13, [{0}, #6 * 32]",
+                "ldp q14, q15, [{0}, #7 * 32]",
+                "ldp q16, q17, [{0}, #8 * 32]",
+                "ldp q18, q19, [{0}, #9 * 32]",
+                "ldp q20, q21, [{0}, #10 * 32]",
+                "ldp q22, q23, [{0}, #11 * 32]",
+                "ldp q24, q25, [{0}, #12 * 32]",
+                "ldp q26, q27, [{0}, #13 * 32]",
+                "ldp q28, q29, [{0}, #14 * 32]",
+                "ldp q30, q31, [{0}, #15 * 32]",
+                in(reg) self.vregs.as_ptr(),
+                options(nostack, readonly)
+            );
+            asm!(".arch_extension fp", "msr FPSR, {0}", in(reg) self.fpsr);
+            asm!(".arch_extension fp", "msr FPCR, {0}", in(reg) self.fpcr);
+        }
+    }
+}
+
+/// The maximum SVE vector length allowed by the architecture, in bytes.
+pub const SVE_VL_MAX: usize = 256;
+
+/// The Scalable Vector Extension (SVE) register file of a PE.
+///
+/// This includes
+/// * the 32 scalable vector registers `Z0`-`Z31`,
+/// * the 16 predicate registers `P0`-`P15`,
+/// * the first-fault register (FFR),
+/// * the SVE control register for EL1 (ZCR_EL1).
+///
+/// The `Z` registers are stored back to back with a stride of the vector length (VL) in effect
+/// when they are stored, and the `P` registers and FFR with a stride of VL / 8. So the same VL
+/// must be in effect when restoring them. FPSR and FPCR are not included, they are kept in
+/// [`FpSimdRegisters`].
+#[repr(C)]
+#[repr(align(16))]
+#[derive(Copy, Clone, Debug)]
+pub struct SveRegisters {
+    /// The scalable vector registers `Z0`-`Z31`.
+    pub zregs: [u8; 32 * SVE_VL_MAX],
+    /// The predicate registers `P0`-`P15`.
+    pub pregs: [u8; 16 * SVE_VL_MAX / 8],
+    /// The first-fault register.
+    pub ffr: [u8; SVE_VL_MAX / 8],
+    /// The SVE control register for EL1.
+    pub zcr_el1: u64,
+}
+
+impl Default for SveRegisters {
+    fn default() -> Self {
+        Self {
+            zregs: [0; 32 * SVE_VL_MAX],
+            pregs: [0; 16 * SVE_VL_MAX / 8],
+            ffr: [0; SVE_VL_MAX / 8],
+            zcr_el1: 0,
+        }
+    }
+}
+
+impl SveRegisters {
+    /// Stores the current SVE registers of the PE into this structure.
+    ///
+    /// # Safety
+    ///
+    /// SVE accesses must not be trapped at the current exception level,
+    /// i.e. `CPTR_EL2.TZ` must be clear.
+    pub(crate) unsafe fn store(&mut self) {
+        unsafe {
+            asm!(
+                ".arch_extension sve",
+                "str z0, [{z}, #0, mul vl]",
+                "str z1, [{z}, #1, mul vl]",
+                "str z2, [{z}, #2, mul vl]",
+                "str z3, [{z}, #3, mul vl]",
+                "str z4, [{z}, #4, mul vl]",
+                "str z5, [{z}, #5, mul vl]",
+                "str z6, [{z}, #6, mul vl]",
+                "str z7, [{z}, #7, mul vl]",
+                "str z8, [{z}, #8, mul vl]",
+                "str z9, [{z}, #9, mul vl]",
+                "str z10, [{z}, #10, mul vl]",
+                "str z11, [{z}, #11, mul vl]",
+                "str z12, [{z}, #12, mul vl]",
+                "str z13, [{z}, #13, mul vl]",
+                "str z14, [{z}, #14, mul vl]",
+                "str z15, [{z}, #15, mul vl]",
+                "str z16, [{z}, #16, mul vl]",
+                "str z17, [{z}, #17, mul vl]",
+                "str z18, [{z}, #18, mul vl]",
+                "str z19, [{z}, #19, mul vl]",
+                "str z20, [{z}, #20, mul vl]",
+                "str z21, [{z}, #21, mul vl]",
+                "str z22, [{z}, #22, mul vl]",
+                "str z23, [{z}, #23, mul vl]",
+                "str z24, [{z}, #24, mul vl]",
+                "str z25, [{z}, #25, mul vl]",
+                "str z26, [{z}, #26, mul vl]",
+                "str z27, [{z}, #27, mul vl]",
+                "str z28, [{z}, #28, mul vl]",
+                "str z29, [{z}, #29, mul vl]",
+                "str z30, [{z}, #30, mul vl]",
+                "str z31, [{z}, #31, mul vl]",
+                "str p0, [{p}, #0, mul vl]",
+                "str p1, [{p}, #1, mul vl]",
+                "str p2, [{p}, #2, mul vl]",
+                "str p3, [{p}, #3, mul vl]",
+                "str p4, [{p}, #4, mul vl]",
+                "str p5, [{p}, #5, mul vl]",
+                "str p6, [{p}, #6, mul vl]",
+                "str p7, [{p}, #7, mul vl]",
+                "str p8, [{p}, #8, mul vl]",
+                "str p9, [{p}, #9, mul vl]",
+                "str p10, [{p}, #10, mul vl]",
+                "str p11, [{p}, #11, mul vl]",
+                "str p12, [{p}, #12, mul vl]",
+                "str p13, [{p}, #13, mul vl]",
+                "str p14, [{p}, #14, mul vl]",
+                "str p15, [{p}, #15, mul vl]",
+                // `P0` has been saved, use it to save FFR.
+                "rdffr p0.b",
+                "str p0, [{ffr}]",
+                "ldr p0, [{p}]",
+                z = in(reg) self.zregs.as_mut_ptr(),
+                p = in(reg) self.pregs.as_mut_ptr(),
+                ffr = in(reg) self.ffr.as_mut_ptr(),
+                options(nostack)
+            );
+            // ZCR_EL1
+            asm!("mrs {0}, S3_0_C1_C2_0", out(reg) self.zcr_el1);
+        }
+    }
+
+    /// Restores the SVE registers of the PE from this structure.
+    ///
+    /// Note that this overwrites the `V` registers, which are the low 128 bits of the `Z`
+    /// registers.
+    ///
+    /// # Safety
+    ///
+    /// SVE accesses must not be trapped at the current exception level,
+    /// i.e. `CPTR_EL2.TZ` must be clear.
+    pub(crate) unsafe fn restore(&self) {
+        unsafe {
+            asm!(
+                ".arch_extension sve",
+                "ldr z0, [{z}, #0, mul vl]",
+                "ldr z1, [{z}, #1, mul vl]",
+                "ldr z2, [{z}, #2, mul vl]",
+                "ldr z3, [{z}, #3, mul vl]",
+                "ldr z4, [{z}, #4, mul vl]",
+                "ldr z5, [{z}, #5, mul vl]",
+                "ldr z6, [{z}, #6, mul vl]",
+                "ldr z7, [{z}, #7, mul vl]",
+                "ldr z8, [{z}, #8, mul vl]",
+                "ldr z9, [{z}, #9, mul vl]",
+                "ldr z10, [{z}, #10, mul vl]",
+                "ldr z11, [{z}, #11, mul vl]",
+                "ldr z12, [{z}, #12, mul vl]",
+                "ldr z13, [{z}, #13, mul vl]",
+                "ldr z14, [{z}, #14, mul vl]",
+                "ldr z15, [{z}, #15, mul vl]",
+                "ldr z16, [{z}, #16, mul vl]",
+                "ldr z17, [{z}, #17, mul vl]",
+                "ldr z18, [{z}, #18, mul vl]",
+                "ldr z19, [{z}, #19, mul vl]",
+                "ldr z20, [{z}, #20, mul vl]",
+                "ldr z21, [{z}, #21, mul vl]",
+                "ldr z22, [{z}, #22, mul vl]",
+                "ldr z23, [{z}, #23, mul vl]",
+                "ldr z24, [{z}, #24, mul vl]",
+                "ldr z25, [{z}, #25, mul vl]",
+                "ldr z26, [{z}, #26, mul vl]",
+                "ldr z27, [{z}, #27, mul vl]",
+                "ldr z28, [{z}, #28, mul vl]",
+                "ldr z29, [{z}, #29, mul vl]",
+                "ldr z30, [{z}, #30, mul vl]",
+                "ldr z31, [{z}, #31, mul vl]",
+                "ldr p0, [{ffr}]",
+                "wrffr p0.b",
+                "ldr p0, [{p}, #0, mul vl]",
+                "ldr p1, [{p}, #1, mul vl]",
+                "ldr p2, [{p}, #2, mul vl]",
+                "ldr p3, [{p}, #3, mul vl]",
+                "ldr p4, [{p}, #4, mul vl]",
+                "ldr p5, [{p}, #5, mul vl]",
+                "ldr p6, [{p}, #6, mul vl]",
+                "ldr p7, [{p}, #7, mul vl]",
+                "ldr p8, [{p}, #8, mul vl]",
+                "ldr p9, [{p}, #9, mul vl]",
+                "ldr p10, [{p}, #10, mul vl]",
+                "ldr p11, [{p}, #11, mul vl]",
+                "ldr p12, [{p}, #12, mul vl]",
+                "ldr p13, [{p}, #13, mul vl]",
+                "ldr p14, [{p}, #14, mul vl]",
+                "ldr p15, [{p}, #15, mul vl]",
+                z = in(reg) self.zregs.as_ptr(),
+                p = in(reg) self.pregs.as_ptr(),
+                ffr = in(reg) self.ffr.as_ptr(),
+                options(nostack, readonly)
+            );
+            // ZCR_EL1
+            asm!("msr S3_0_C1_C2_0, {0}", in(reg) self.zcr_el1);
+        }
+    }
+
+    /// Replaces the low 128 bits of each `Z` register, stored with the vector length `vl` in
+    /// bytes, by the corresponding `V` register of `vregs`.
+    ///
+    /// The `V` registers may be changed without the `Z` registers, e.g. by the VMM, so this keeps
+    /// [`Self::restore`] from loading stale values into them.
+    pub(crate) fn merge_vregs(&mut self, vregs: &[u128; 32], vl: usize) {
+        for (zreg, vreg) in self.zregs.chunks_exact_mut(vl).zip(vregs) {
+            zreg[..16].copy_from_slice(&vreg.to_le_bytes());
+        }
+    }
+}
+
 /// Represents the VM context for a guest virtual machine in a hypervisor environment.
 ///
 /// The `GuestSystemRegisters` structure contains various registers and states needed to manage
@@ -160,50 +514,68 @@ impl Aarch64ContextFrame {
 #[derive(Debug, Clone, Copy, Default)]
 pub struct GuestSystemRegisters {
     // generic timer
-    pub cntvoff_el2: u64,
-    cntp_cval_el0: u64,
-    cntv_cval_el0: u64,
-    pub cntkctl_el1: u32,
-    pub cntvct_el0: u64,
-    cntp_ctl_el0: u32,
-    cntv_ctl_el0: u32,
-    cntp_tval_el0: u32,
-    cntv_tval_el0: u32,
-    pub cnthctl_el2: u64,
+    pub(crate) cntvoff_el2: u64,
+    /// Only switched if the EL1 physical timer is passed through, see [`Self::ptimer_passthrough`].
+    pub(crate) cntp_cval_el0: u64,
+    pub(crate) cntv_cval_el0: u64,
+    pub(crate) cntkctl_el1: u32,
+    pub(crate) cntvct_el0: u64,
+    pub(crate) cntp_ctl_el0: u32,
+    pub(crate) cntv_ctl_el0: u32,
+    pub(crate) cnthctl_el2: u64,
 
     // vpidr and vmpidr
     vpidr_el2: u32,
-    pub vmpidr_el2: u64,
+    pub(crate) vmpidr_el2: u64,
 
     // 64bit EL1/EL0 register
-    pub sp_el0: u64,
-    sp_el1: u64,
-    elr_el1: u64,
-    spsr_el1: u32,
-    pub sctlr_el1: u32,
+    pub(crate) sp_el0: u64,
+    pub(crate) sp_el1: u64,
+    pub(crate) elr_el1: u64,
+    pub(crate) spsr_el1: u32,
+    pub(crate) sctlr_el1: u32,
     actlr_el1: u64,
     cpacr_el1: u32,
     ttbr0_el1: u64,
     ttbr1_el1: u64,
-    tcr_el1: u64,
-    esr_el1: u32,
-    far_el1: u64,
+    pub(crate) tcr_el1: u64,
+    pub(crate) esr_el1: u32,
+    afsr0_el1: u64,
+    afsr1_el1: u64,
+    pub(crate) far_el1: u64,
     par_el1: u64,
     mair_el1: u64,
     amair_el1: u64,
-    vbar_el1: u64,
+    pub(crate) vbar_el1: u64,
     contextidr_el1: u32,
     tpidr_el0: u64,
     tpidr_el1: u64,
     tpidrro_el0: u64,
 
     // hypervisor context
-    pub hcr_el2: u64,
-    pub vttbr_el2: u64,
-    cptr_el2: u64,
-    hstr_el2: u64,
-    pub pmcr_el0: u64,
-    pub vtcr_el2: u64,
+    pub(crate) hcr_el2: u64,
+    pub(crate) vttbr_el2: u64,
+    pub(crate) cptr_el2: u64,
+    pub(crate) hstr_el2: u64,
+    /// `MDCR_EL2`, with `HPMN` and `HPME` taken from the host.
+    pub(crate) mdcr_el2: u64,
+    pub(crate) pmcr_el0: u64,
+    pub(crate) vtcr_el2: u64,
+
+    // AArch32 EL1 registers, only switched if EL1 is AArch32
+    pub(crate) spsr_abt: u32,
+    pub(crate) spsr_und: u32,
+    spsr_irq: u32,
+    spsr_fiq: u32,
+    dacr32_el2: u32,
+    pub(crate) ifsr32_el2: u32,
+    /// `FPEXC32_EL2`, switched together with the FP/SIMD registers as it is trapped by `CPTR_EL2.TFP`.
+    pub(crate) fpexc32_el2: u32,
+
+    /// `VSESR_EL2`, the syndrome of the virtual SError pending by `HCR_EL2.VSE`.
+    ///
+    /// Only restored when a virtual SError is pending and the CPU implements RAS.
+    pub(crate) vsesr_el2: u64,
 
     // exception
     far_el2: u64,
@@ -220,19 +592,221 @@ impl GuestSystemRegisters {
         *self = GuestSystemRegisters::default()
     }
 
+    /// Returns whether the EL1 physical timer is passed through to the guest, according to
+    /// `CNTHCTL_EL2.EL1PCEN`.
+    pub fn ptimer_passthrough(&self) -> bool {
+        CNTHCTL_EL2::EL1PCEN.is_set(self.cnthctl_el2)
+    }
+
+    /// Returns whether EL1 of the guest is AArch32, according to `HCR_EL2.RW`.
+    pub fn el1_is_aarch32(&self) -> bool {
+        !HCR_EL2::RW.is_set(self.hcr_el2)
+    }
+
+    /// Returns the value of the guest register `reg`, `None` if it is not saved in this
+    /// structure, e.g. the general-purpose registers, see [`VCpuReg`].
+    pub fn reg(&self, reg: VCpuReg) -> Option<u64> {
+        let value = match reg {
+            VCpuReg::SpEl0 => self.sp_el0,
+            VCpuReg::SpEl1 => self.sp_el1,
+            VCpuReg::ElrEl1 => self.elr_el1,
+            VCpuReg::SpsrEl1 => self.spsr_el1 as u64,
+            VCpuReg::SctlrEl1 => self.sctlr_el1 as u64,
+            VCpuReg::ActlrEl1 => self.actlr_el1,
+            VCpuReg::CpacrEl1 => self.cpacr_el1 as u64,
+            VCpuReg::Ttbr0El1 => self.ttbr0_el1,
+            VCpuReg::Ttbr1El1 => self.ttbr1_el1,
+            VCpuReg::TcrEl1 => self.tcr_el1,
+            VCpuReg::EsrEl1 => self.esr_el1 as u64,
+            VCpuReg::Afsr0El1 => self.afsr0_el1,
+            VCpuReg::Afsr1El1 => self.afsr1_el1,
+            VCpuReg::FarEl1 => self.far_el1,
+            VCpuReg::ParEl1 => self.par_el1,
+            VCpuReg::MairEl1 => self.mair_el1,
+            VCpuReg::AmairEl1 => self.amair_el1,
+            VCpuReg::VbarEl1 => self.vbar_el1,
+            VCpuReg::ContextidrEl1 => self.contextidr_el1 as u64,
+            VCpuReg::TpidrEl0 => self.tpidr_el0,
+            VCpuReg::TpidrEl1 => self.tpidr_el1,
+            VCpuReg::TpidrroEl0 => self.tpidrro_el0,
+            VCpuReg::CntkctlEl1 => self.cntkctl_el1 as u64,
+            VCpuReg::CntvCtlEl0 => self.cntv_ctl_el0 as u64,
+            VCpuReg::CntvCvalEl0 => self.cntv_cval_el0,
+            VCpuReg::CntpCtlEl0 => self.cntp_ctl_el0 as u64,
+            VCpuReg::CntpCvalEl0 => self.cntp_cval_el0,
+            VCpuReg::CntvoffEl2 => self.cntvoff_el2,
+            VCpuReg::VpidrEl2 => self.vpidr_el2 as u64,
+            VCpuReg::VmpidrEl2 => self.vmpidr_el2,
+            VCpuReg::SpsrAbt => self.spsr_abt as u64,
+            VCpuReg::SpsrUnd => self.spsr_und as u64,
+            VCpuReg::SpsrIrq => self.spsr_irq as u64,
+            VCpuReg::SpsrFiq => self.spsr_fiq as u64,
+            VCpuReg::Dacr32El2 => self.dacr32_el2 as u64,
+            VCpuReg::Ifsr32El2 => self.ifsr32_el2 as u64,
+            VCpuReg::Fpexc32El2 => self.fpexc32_el2 as u64,
+            VCpuReg::PmcrEl0 => self.pmcr_el0,
+            VCpuReg::HcrEl2 => self.hcr_el2,
+            VCpuReg::VttbrEl2 => self.vttbr_el2,
+            VCpuReg::VtcrEl2 => self.vtcr_el2,
+            VCpuReg::CptrEl2 => self.cptr_el2,
+            VCpuReg::HstrEl2 => self.hstr_el2,
+            VCpuReg::MdcrEl2 => self.mdcr_el2,
+            VCpuReg::CnthctlEl2 => self.cnthctl_el2,
+            VCpuReg::VsesrEl2 => self.vsesr_el2,
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    /// Sets the guest register `reg` to `value`, see [`Self::reg`].
+    ///
+    /// Returns `false` if `reg` is not saved in this structure.
+    pub fn set_reg(&mut self, reg: VCpuReg, value: u64) -> bool {
+        match reg {
+            VCpuReg::SpEl0 => self.sp_el0 = value,
+            VCpuReg::SpEl1 => self.sp_el1 = value,
+            VCpuReg::ElrEl1 => self.elr_el1 = value,
+            VCpuReg::SpsrEl1 => self.spsr_el1 = value as u32,
+            VCpuReg::SctlrEl1 => self.sctlr_el1 = value as u32,
+            VCpuReg::ActlrEl1 => self.actlr_el1 = value,
+            VCpuReg::CpacrEl1 => self.cpacr_el1 = value as u32,
+            VCpuReg::Ttbr0El1 => self.ttbr0_el1 = value,
+            VCpuReg::Ttbr1El1 => self.ttbr1_el1 = value,
+            VCpuReg::TcrEl1 => self.tcr_el1 = value,
+            VCpuReg::EsrEl1 => self.esr_el1 = value as u32,
+            VCpuReg::Afsr0El1 => self.afsr0_el1 = value,
+            VCpuReg::Afsr1El1 => self.afsr1_el1 = value,
+            VCpuReg::FarEl1 => self.far_el1 = value,
+            VCpuReg::ParEl1 => self.par_el1 = value,
+            VCpuReg::MairEl1 => self.mair_el1 = value,
+            VCpuReg::AmairEl1 => self.amair_el1 = value,
+            VCpuReg::VbarEl1 => self.vbar_el1 = value,
+            VCpuReg::ContextidrEl1 => self.contextidr_el1 = value as u32,
+            VCpuReg::TpidrEl0 => self.tpidr_el0 = value,
+            VCpuReg::TpidrEl1 => self.tpidr_el1 = value,
+            VCpuReg::TpidrroEl0 => self.tpidrro_el0 = value,
+            VCpuReg::CntkctlEl1 => self.cntkctl_el1 = value as u32,
+            VCpuReg::CntvCtlEl0 => self.cntv_ctl_el0 = value as u32,
+            VCpuReg::CntvCvalEl0 => self.cntv_cval_el0 = value,
+            VCpuReg::CntpCtlEl0 => self.cntp_ctl_el0 = value as u32,
+            VCpuReg::CntpCvalEl0 => self.cntp_cval_el0 = value,
+            VCpuReg::CntvoffEl2 => self.cntvoff_el2 = value,
+            VCpuReg::VpidrEl2 => self.vpidr_el2 = value as u32,
+            VCpuReg::VmpidrEl2 => self.vmpidr_el2 = value,
+            VCpuReg::SpsrAbt => self.spsr_abt = value as u32,
+            VCpuReg::SpsrUnd => self.spsr_und = value as u32,
+            VCpuReg::SpsrIrq => self.spsr_irq = value as u32,
+            VCpuReg::SpsrFiq => self.spsr_fiq = value as u32,
+            VCpuReg::Dacr32El2 => self.dacr32_el2 = value as u32,
+            VCpuReg::Ifsr32El2 => self.ifsr32_el2 = value as u32,
+            VCpuReg::Fpexc32El2 => self.fpexc32_el2 = value as u32,
+            VCpuReg::PmcrEl0 => self.pmcr_el0 = value,
+            VCpuReg::HcrEl2 => self.hcr_el2 = value,
+            VCpuReg::VttbrEl2 => self.vttbr_el2 = value,
+            VCpuReg::VtcrEl2 => self.vtcr_el2 = value,
+            VCpuReg::CptrEl2 => self.cptr_el2 = value,
+            VCpuReg::HstrEl2 => self.hstr_el2 = value,
+            VCpuReg::MdcrEl2 => self.mdcr_el2 = value,
+            VCpuReg::CnthctlEl2 => self.cnthctl_el2 = value,
+            VCpuReg::VsesrEl2 => self.vsesr_el2 = value,
+            _ => return false,
+        }
+        true
+    }
+
+    /// Reads the virtual memory control register `addr` of the guest, as a trapped MRS (or MRC
+    /// from AArch32) would, see [`SysRegAddr`].
+    ///
+    /// Returns `None` if `addr` is not a virtual memory control register switched with the vCPU.
+    pub fn vm_control_read(&self, addr: SysRegAddr) -> Option<u64> {
+        let value = match addr.addr() {
+            SYSREG_SCTLR_EL1 | SYSREG_CP15_SCTLR => self.sctlr_el1 as u64,
+            SYSREG_TTBR0_EL1 => self.ttbr0_el1,
+            SYSREG_TTBR1_EL1 => self.ttbr1_el1,
+            SYSREG_TCR_EL1 => self.tcr_el1,
+            SYSREG_ESR_EL1 | SYSREG_CP15_DFSR => self.esr_el1 as u64,
+            SYSREG_AFSR0_EL1 => self.afsr0_el1,
+            SYSREG_AFSR1_EL1 => self.afsr1_el1,
+            SYSREG_FAR_EL1 => self.far_el1,
+            SYSREG_MAIR_EL1 => self.mair_el1,
+            SYSREG_AMAIR_EL1 => self.amair_el1,
+            SYSREG_CONTEXTIDR_EL1 | SYSREG_CP15_CONTEXTIDR => self.contextidr_el1 as u64,
+            // AArch32 registers mapped to halves of AArch64 ones.
+            SYSREG_CP15_TTBR0 => self.ttbr0_el1 as u32 as u64,
+            SYSREG_CP15_TTBR1 => self.ttbr1_el1 as u32 as u64,
+            SYSREG_CP15_TTBCR => self.tcr_el1 as u32 as u64,
+            SYSREG_CP15_DFAR => self.far_el1 as u32 as u64,
+            SYSREG_CP15_IFAR => self.far_el1 >> 32,
+            SYSREG_CP15_MAIR0 => self.mair_el1 as u32 as u64,
+            SYSREG_CP15_MAIR1 => self.mair_el1 >> 32,
+            SYSREG_CP15_AMAIR0 => self.amair_el1 as u32 as u64,
+            SYSREG_CP15_AMAIR1 => self.amair_el1 >> 32,
+            SYSREG_CP15_DACR => self.dacr32_el2 as u64,
+            SYSREG_CP15_IFSR => self.ifsr32_el2 as u64,
+            SYSREG_CP15_ADFSR => self.afsr0_el1 as u32 as u64,
+            SYSREG_CP15_AIFSR => self.afsr1_el1 as u32 as u64,
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    /// Writes `value` to the virtual memory control register `addr` of the guest, as a trapped
+    /// MSR (or MCR from AArch32) would, see [`SysRegAddr`].
+    ///
+    /// Returns `false` if `addr` is not a virtual memory control register switched with the vCPU.
+    pub fn vm_control_write(&mut self, addr: SysRegAddr, value: u64) -> bool {
+        fn set_half(reg: &mut u64, high: bool, value: u64) {
+            let shift = if high { 32 } else { 0 };
+            *reg = (*reg & !(0xffff_ffff << shift)) | ((value & 0xffff_ffff) << shift);
+        }
+
+        match addr.addr() {
+            SYSREG_SCTLR_EL1 | SYSREG_CP15_SCTLR => self.sctlr_el1 = value as u32,
+            SYSREG_TTBR0_EL1 => self.ttbr0_el1 = value,
+            SYSREG_TTBR1_EL1 => self.ttbr1_el1 = value,
+            SYSREG_TCR_EL1 => self.tcr_el1 = value,
+            SYSREG_ESR_EL1 | SYSREG_CP15_DFSR => self.esr_el1 = value as u32,
+            SYSREG_AFSR0_EL1 => self.afsr0_el1 = value,
+            SYSREG_AFSR1_EL1 => self.afsr1_el1 = value,
+            SYSREG_FAR_EL1 => self.far_el1 = value,
+            SYSREG_MAIR_EL1 => self.mair_el1 = value,
+            SYSREG_AMAIR_EL1 => self.amair_el1 = value,
+            SYSREG_CONTEXTIDR_EL1 | SYSREG_CP15_CONTEXTIDR => self.contextidr_el1 = value as u32,
+            SYSREG_CP15_TTBR0 => set_half(&mut self.ttbr0_el1, false, value),
+            SYSREG_CP15_TTBR1 => set_half(&mut self.ttbr1_el1, false, value),
+            SYSREG_CP15_TTBCR => set_half(&mut self.tcr_el1, false, value),
+            SYSREG_CP15_DFAR => set_half(&mut self.far_el1, false, value),
+            SYSREG_CP15_IFAR => set_half(&mut self.far_el1, true, value),
+            SYSREG_CP15_MAIR0 => set_half(&mut self.mair_el1, false, value),
+            SYSREG_CP15_MAIR1 => set_half(&mut self.mair_el1, true, value),
+            SYSREG_CP15_AMAIR0 => set_half(&mut self.amair_el1, false, value),
+            SYSREG_CP15_AMAIR1 => set_half(&mut self.amair_el1, true, value),
+            SYSREG_CP15_DACR => self.dacr32_el2 = value as u32,
+            SYSREG_CP15_IFSR => self.ifsr32_el2 = value as u32,
+            SYSREG_CP15_ADFSR => set_half(&mut self.afsr0_el1, false, value),
+            SYSREG_CP15_AIFSR => set_half(&mut self.afsr1_el1, false, value),
+            _ => return false,
+        }
+        true
+    }
+
     /// Stores the current values of all relevant registers into the `GuestSystemRegisters` structure.
     ///
     /// This method uses inline assembly to read the values of various system registers
     /// and stores them in the corresponding fields of the `GuestSystemRegisters` structure.
-    pub unsafe fn store(&mut self) {
+    pub(crate) unsafe fn store(&mut self) {
         unsafe {
+            // Save the timers and disable them, so that they can not fire while the host runs.
             asm!("mrs {0}, CNTVOFF_EL2", out(reg) self.cntvoff_el2);
+            asm!("mrs {0:x}, CNTV_CTL_EL0", out(reg) self.cntv_ctl_el0);
             asm!("mrs {0}, CNTV_CVAL_EL0", out(reg) self.cntv_cval_el0);
+            asm!("msr CNTV_CTL_EL0, xzr");
+            if self.ptimer_passthrough() {
+                asm!("mrs {0:x}, CNTP_CTL_EL0", out(reg) self.cntp_ctl_el0);
+                asm!("mrs {0}, CNTP_CVAL_EL0", out(reg) self.cntp_cval_el0);
+                asm!("msr CNTP_CTL_EL0, xzr");
+            }
             asm!("mrs {0:x}, CNTKCTL_EL1", out(reg) self.cntkctl_el1);
-            asm!("mrs {0:x}, CNTP_CTL_EL0", out(reg) self.cntp_ctl_el0);
-            asm!("mrs {0:x}, CNTV_CTL_EL0", out(reg) self.cntv_ctl_el0);
-            asm!("mrs {0:x}, CNTP_TVAL_EL0", out(reg) self.cntp_tval_el0);
-            asm!("mrs {0:x}, CNTV_TVAL_EL0", out(reg) self.cntv_tval_el0);
             asm!("mrs {0}, CNTVCT_EL0", out(reg) self.cntvct_el0);
             asm!("mrs {0}, CNTHCTL_EL2", out(reg) self.cnthctl_el2);
             // MRS!("self.vpidr_el2, VPIDR_EL2, "x");
@@ -248,6 +822,8 @@ impl GuestSystemRegisters {
             asm!("mrs {0}, TTBR1_EL1", out(reg) self.ttbr1_el1);
             asm!("mrs {0}, TCR_EL1", out(reg) self.tcr_el1);
             asm!("mrs {0:x}, ESR_EL1", out(reg) self.esr_el1);
+            asm!("mrs {0}, AFSR0_EL1", out(reg) self.afsr0_el1);
+            asm!("mrs {0}, AFSR1_EL1", out(reg) self.afsr1_el1);
             asm!("mrs {0}, FAR_EL1", out(reg) self.far_el1);
             asm!("mrs {0}, PAR_EL1", out(reg) self.par_el1);
             asm!("mrs {0}, MAIR_EL1", out(reg) self.mair_el1);
@@ -263,6 +839,15 @@ impl GuestSystemRegisters {
             asm!("mrs {0}, VTTBR_EL2", out(reg) self.vttbr_el2);
             asm!("mrs {0}, HCR_EL2", out(reg) self.hcr_el2);
             asm!("mrs {0}, ACTLR_EL1", out(reg) self.actlr_el1);
+
+            if self.el1_is_aarch32() {
+                asm!("mrs {0:x}, SPSR_abt", out(reg) self.spsr_abt);
+                asm!("mrs {0:x}, SPSR_und", out(reg) self.spsr_und);
+                asm!("mrs {0:x}, SPSR_irq", out(reg) self.spsr_irq);
+                asm!("mrs {0:x}, SPSR_fiq", out(reg) self.spsr_fiq);
+                asm!("mrs {0:x}, DACR32_EL2", out(reg) self.dacr32_el2);
+                asm!("mrs {0:x}, IFSR32_EL2", out(reg) self.ifsr32_el2);
+            }
             // println!("save sctlr {:x}", self.sctlr_el1);
         }
     }
@@ -275,12 +860,22 @@ impl GuestSystemRegisters {
     ///
     /// Each system register is restored with its corresponding value from the `GuestSystemRegisters`, ensuring
     /// that the virtual machine or thread resumes execution with the correct context.
-    pub unsafe fn restore(&self) {
+    pub(crate) unsafe fn restore(&self) {
         unsafe {
-            asm!("msr CNTV_CVAL_EL0, {0}", in(reg) self.cntv_cval_el0);
+            // The offset and the compare values must be in place before the timers are enabled,
+            // or they may fire with stale values.
+            asm!("msr CNTVOFF_EL2, {0}", in(reg) self.cntvoff_el2);
+            asm!("msr CNTHCTL_EL2, {0}", in(reg) self.cnthctl_el2);
             asm!("msr CNTKCTL_EL1, {0:x}", in (reg) self.cntkctl_el1);
+            asm!("msr CNTV_CVAL_EL0, {0}", in(reg) self.cntv_cval_el0);
+            if self.ptimer_passthrough() {
+                asm!("msr CNTP_CVAL_EL0, {0}", in(reg) self.cntp_cval_el0);
+            }
+            asm!("isb");
             asm!("msr CNTV_CTL_EL0, {0:x}", in (reg) self.cntv_ctl_el0);
-            asm!("msr CNTHCTL_EL2, {0}", in(reg) self.cnthctl_el2);
+            if self.ptimer_passthrough() {
+                asm!("msr CNTP_CTL_EL0, {0:x}", in (reg) self.cntp_ctl_el0);
+            }
             // The restoration of SP_EL0 is done in `exception_return_el2`,
             // which move the value from `self.ctx.sp_el0` to `SP_EL0`.
             // asm!("msr SP_EL0, {0}", in(reg) self.sp_el0);
@@ -293,6 +888,8 @@ impl GuestSystemRegisters {
             asm!("msr TTBR1_EL1, {0}", in(reg) self.ttbr1_el1);
             asm!("msr TCR_EL1, {0}", in(reg) self.tcr_el1);
             asm!("msr ESR_EL1, {0:x}", in(reg) self.esr_el1);
+            asm!("msr AFSR0_EL1, {0}", in(reg) self.afsr0_el1);
+            asm!("msr AFSR1_EL1, {0}", in(reg) self.afsr1_el1);
             asm!("msr FAR_EL1, {0}", in(reg) self.far_el1);
             asm!("msr PAR_EL1, {0}", in(reg) self.par_el1);
             asm!("msr MAIR_EL1, {0}", in(reg) self.mair_el1);
@@ -306,11 +903,254 @@ impl GuestSystemRegisters {
             asm!("msr PMCR_EL0, {0}", in(reg) self.pmcr_el0);
             asm!("msr ACTLR_EL1, {0}", in(reg) self.actlr_el1);
 
+            if self.el1_is_aarch32() {
+                asm!("msr SPSR_abt, {0:x}", in(reg) self.spsr_abt);
+                asm!("msr SPSR_und, {0:x}", in(reg) self.spsr_und);
+                asm!("msr SPSR_irq, {0:x}", in(reg) self.spsr_irq);
+                asm!("msr SPSR_fiq, {0:x}", in(reg) self.spsr_fiq);
+                asm!("msr DACR32_EL2, {0:x}", in(reg) self.dacr32_el2);
+                asm!("msr IFSR32_EL2, {0:x}", in(reg) self.ifsr32_el2);
+            }
+
+            if self.hcr_el2 & HCR_EL2_VSE != 0 && (ID_AA64PFR0_EL1.get() >> 28) & 0xf != 0 {
+                // VSESR_EL2
+                asm!("msr S3_4_C5_C2_3, {0}", in(reg) self.vsesr_el2);
+            }
+
+            asm!("msr HSTR_EL2, {0}", in(reg) self.hstr_el2);
+            asm!("msr MDCR_EL2, {0}", in(reg) self.mdcr_el2);
             asm!("msr VTCR_EL2, {0}", in(reg) self.vtcr_el2);
             asm!("msr VTTBR_EL2, {0}", in(reg) self.vttbr_el2);
             asm!("msr HCR_EL2, {0}", in(reg) self.hcr_el2);
             asm!("msr VMPIDR_EL2, {0}", in(reg) self.vmpidr_el2);
-            asm!("msr CNTVOFF_EL2, {0}", in(reg) self.cntvoff_el2);
+        }
+    }
+}
+
+/// Accesses the `n`-th register of a group of `ICH_*<n>_EL2` registers, whose names have to be
+/// known when assembling.
+///
+/// `read` reads the register into a `u64`, and `write` writes a `u64` to it.
+macro_rules! ich_reg_n {
+    (read, $n:expr, [$($reg:literal),*]) => {{
+        let mut value: u64 = 0;
+        let mut i = 0;
+        $(
+            if i == $n {
+                asm!(concat!("mrs {0}, ", $reg), out(reg) value);
+            }
+            i += 1;
+        )*
+        let _ = i;
+        value
+    }};
+    (write, $n:expr, $value:expr, [$($reg:literal),*]) => {{
+        let mut i = 0;
+        $(
+            if i == $n {
+                asm!(concat!("msr ", $reg, ", {0}"), in(reg) $value);
+            }
+            i += 1;
+        )*
+        let _ = i;
+    }};
+}
+
+/// Names of `ICH_LR<n>_EL2`.
+macro_rules! ich_lr_regs {
+    ($op:ident, $n:expr $(, $value:expr)?) => {
+        ich_reg_n!($op, $n $(, $value)?, [
+            "S3_4_C12_C12_0", "S3_4_C12_C12_1", "S3_4_C12_C12_2", "S3_4_C12_C12_3",
+            "S3_4_C12_C12_4", "S3_4_C12_C12_5", "S3_4_C12_C12_6", "S3_4_C12_C12_7",
+            "S3_4_C12_C13_0", "S3_4_C12_C13_1", "S3_4_C12_C13_2", "S3_4_C12_C13_3",
+            "S3_4_C12_C13_4", "S3_4_C12_C13_5", "S3_4_C12_C13_6", "S3_4_C12_C13_7"
+        ])
+    };
+}
+
+/// Names of `ICH_AP0R<n>_EL2`.
+macro_rules! ich_ap0r_regs {
+    ($op:ident, $n:expr $(, $value:expr)?) => {
+        ich_reg_n!($op, $n $(, $value)?, [
+            "S3_4_C12_C8_0", "S3_4_C12_C8_1", "S3_4_C12_C8_2", "S3_4_C12_C8_3"
+        ])
+    };
+}
+
+/// Names of `ICH_AP1R<n>_EL2`.
+macro_rules! ich_ap1r_regs {
+    ($op:ident, $n:expr $(, $value:expr)?) => {
+        ich_reg_n!($op, $n $(, $value)?, [
+            "S3_4_C12_C9_0", "S3_4_C12_C9_1", "S3_4_C12_C9_2", "S3_4_C12_C9_3"
+        ])
+    };
+}
+
+/// The maximum number of GICv3 list registers.
+pub const ICH_LR_MAX: usize = 16;
+
+/// `ICH_LR<n>_EL2.State`, bits \[63:62\].
+const ICH_LR_STATE_SHIFT: u64 = 62;
+const ICH_LR_STATE_MASK: u64 = 0b11 << ICH_LR_STATE_SHIFT;
+const ICH_LR_STATE_PENDING: u64 = 0b01 << ICH_LR_STATE_SHIFT;
+/// `ICH_LR<n>_EL2.Group`, set for Group 1 interrupts.
+const ICH_LR_GROUP1: u64 = 1 << 60;
+/// `ICH_LR<n>_EL2.Priority`, bits \[55:48\].
+const ICH_LR_PRIORITY_SHIFT: u64 = 48;
+/// `ICH_LR<n>_EL2.vINTID`, bits \[31:0\].
+const ICH_LR_VINTID_MASK: u64 = 0xffff_ffff;
+/// The priority of interrupts injected through [`GicV3Registers::inject`], the default priority
+/// used by Linux.
+const ICH_LR_DEFAULT_PRIORITY: u64 = 0xa0;
+/// `ICH_HCR_EL2.En`, enables the virtual CPU interface.
+const ICH_HCR_EN: u64 = 1 << 0;
+
+/// The state of a virtual interrupt in a GICv3 list register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VirtualIrqState {
+    /// The interrupt is pending, and not yet acknowledged by the guest.
+    Pending,
+    /// The interrupt has been acknowledged by the guest and is being handled.
+    Active,
+    /// The interrupt is being handled by the guest, and is pending again.
+    PendingActive,
+}
+
+/// The state of the GICv3 virtual CPU interface of a vCPU, the `ICH_*_EL2` registers.
+///
+/// Only the list registers and active priority registers implemented by the CPU, according to
+/// `ICH_VTR_EL2`, are switched.
+#[repr(C)]
+#[derive(Clone, Debug, Copy, Default)]
+pub struct GicV3Registers {
+    /// `ICH_HCR_EL2`.
+    pub ich_hcr_el2: u64,
+    /// `ICH_VMCR_EL2`, which holds the state of the `ICC_*` registers of the guest.
+    pub ich_vmcr_el2: u64,
+    /// `ICH_AP0R<n>_EL2`.
+    pub ich_ap0r_el2: [u32; 4],
+    /// `ICH_AP1R<n>_EL2`.
+    pub ich_ap1r_el2: [u32; 4],
+    /// `ICH_LR<n>_EL2`.
+    pub ich_lr_el2: [u64; ICH_LR_MAX],
+    /// The number of list registers implemented, from `ICH_VTR_EL2.ListRegs`.
+    nr_lrs: usize,
+    /// The number of active priority registers implemented in each group, from
+    /// `ICH_VTR_EL2.PREbits`.
+    nr_aprs: usize,
+}
+
+impl GicV3Registers {
+    /// Creates the state of an enabled virtual CPU interface with no interrupt in flight, for
+    /// the GICv3 of the current CPU.
+    ///
+    /// Must be called at EL2, on a CPU with the GICv3 system register interface.
+    pub fn new() -> Self {
+        let vtr: u64;
+        unsafe { asm!("mrs {0}, S3_4_C12_C11_1", out(reg) vtr) }; // ICH_VTR_EL2
+        let nr_lrs = (vtr & 0x1f) as usize + 1;
+        // 5, 6 and 7 preemption bits need 1, 2 and 4 active priority registers respectively.
+        let nr_aprs = 1 << (((vtr >> 26) & 0b111) as usize).saturating_sub(4);
+
+        Self {
+            ich_hcr_el2: ICH_HCR_EN,
+            nr_lrs,
+            nr_aprs,
+            ..Default::default()
+        }
+    }
+
+    /// Copies the register values of `other`, keeping the numbers of list and active priority
+    /// registers of `self`, which are the ones of the hardware.
+    pub(crate) fn copy_state_from(&mut self, other: &Self) {
+        *self = Self {
+            nr_lrs: self.nr_lrs,
+            nr_aprs: self.nr_aprs,
+            ..*other
+        };
+    }
+
+    /// Returns the number of list registers implemented.
+    pub fn nr_lrs(&self) -> usize {
+        self.nr_lrs
+    }
+
+    /// Returns an iterator over the indices of the list registers holding no interrupt.
+    pub fn free_lrs(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.nr_lrs).filter(|&n| self.ich_lr_el2[n] & ICH_LR_STATE_MASK == 0)
+    }
+
+    /// Returns the state of the virtual interrupt `vintid`, or `None` if it is in no list
+    /// register.
+    pub fn irq_state(&self, vintid: u32) -> Option<VirtualIrqState> {
+        self.ich_lr_el2[..self.nr_lrs]
+            .iter()
+            .filter(|&&lr| lr & ICH_LR_VINTID_MASK == vintid as u64)
+            .find_map(|&lr| match (lr & ICH_LR_STATE_MASK) >> ICH_LR_STATE_SHIFT {
+                0b01 => Some(VirtualIrqState::Pending),
+                0b10 => Some(VirtualIrqState::Active),
+                0b11 => Some(VirtualIrqState::PendingActive),
+                _ => None,
+            })
+    }
+
+    /// Makes the virtual Group 1 interrupt `vintid` pending in a list register.
+    ///
+    /// Returns [`AxError::ResourceBusy`](axerrno::AxError::ResourceBusy) if all list registers
+    /// are in use.
+    pub fn inject(&mut self, vintid: u32) -> axerrno::AxResult {
+        let lrs = &mut self.ich_lr_el2[..self.nr_lrs];
+        // An interrupt already in a list register only needs to be made pending.
+        if let Some(lr) = lrs
+            .iter_mut()
+            .find(|lr| **lr & ICH_LR_STATE_MASK != 0 && **lr & ICH_LR_VINTID_MASK == vintid as u64)
+        {
+            *lr |= ICH_LR_STATE_PENDING;
+            return Ok(());
+        }
+
+        let Some(lr) = lrs.iter_mut().find(|lr| **lr & ICH_LR_STATE_MASK == 0) else {
+            return axerrno::ax_err!(ResourceBusy, "no free GICv3 list register");
+        };
+        *lr = ICH_LR_STATE_PENDING
+            | ICH_LR_GROUP1
+            | (ICH_LR_DEFAULT_PRIORITY << ICH_LR_PRIORITY_SHIFT)
+            | vintid as u64;
+        Ok(())
+    }
+
+    /// Stores the state of the virtual CPU interface of the current CPU, and then disables it
+    /// and clears its list registers, so that nothing of the guest is left to the host or to
+    /// another guest.
+    pub(crate) unsafe fn store(&mut self) {
+        unsafe {
+            asm!("mrs {0}, S3_4_C12_C11_0", out(reg) self.ich_hcr_el2); // ICH_HCR_EL2
+            asm!("mrs {0}, S3_4_C12_C11_7", out(reg) self.ich_vmcr_el2); // ICH_VMCR_EL2
+            for n in 0..self.nr_aprs {
+                self.ich_ap0r_el2[n] = ich_ap0r_regs!(read, n) as u32;
+                self.ich_ap1r_el2[n] = ich_ap1r_regs!(read, n) as u32;
+            }
+            for n in 0..self.nr_lrs {
+                self.ich_lr_el2[n] = ich_lr_regs!(read, n);
+                ich_lr_regs!(write, n, 0u64);
+            }
+            asm!("msr S3_4_C12_C11_0, xzr"); // ICH_HCR_EL2
+        }
+    }
+
+    /// Restores the state of the virtual CPU interface to the current CPU, enabling it if the
+    /// guest has it enabled.
+    pub(crate) unsafe fn restore(&self) {
+        unsafe {
+            asm!("msr S3_4_C12_C11_7, {0}", in(reg) self.ich_vmcr_el2); // ICH_VMCR_EL2
+            for n in 0..self.nr_aprs {
+                ich_ap0r_regs!(write, n, self.ich_ap0r_el2[n] as u64);
+                ich_ap1r_regs!(write, n, self.ich_ap1r_el2[n] as u64);
+            }
+            for n in 0..self.nr_lrs {
+                ich_lr_regs!(write, n, self.ich_lr_el2[n]);
+            }
+            asm!("msr S3_4_C12_C11_0, {0}", in(reg) self.ich_hcr_el2); // ICH_HCR_EL2
         }
     }
 }
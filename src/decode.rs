@@ -0,0 +1,621 @@
+// Copyright 2025 The Axvisor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Decoding of A64 load/store instructions, for data aborts without a valid instruction
+//! syndrome.
+//!
+//! `ESR_EL2.ISV` is only set for loads and stores of a single general-purpose register without
+//! writeback. Guests accessing MMIO with other instructions, e.g. `LDP`/`STP`, pre- or
+//! post-indexed addressing, or SIMD&FP registers (which compilers emit for `memcpy` or struct
+//! copies), have to be emulated from the instruction itself. The decoder only works on the raw
+//! instruction word, fetching the instruction and accessing the registers is up to the caller.
+
+/// How the address of a load/store is formed from its base register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum AddressMode {
+    /// `[Xn, offset]`, the base register is not updated.
+    Offset,
+    /// `[Xn, #imm]!`, the access is at `Xn + imm`, which is written back to `Xn`.
+    PreIndex,
+    /// `[Xn], #imm`, the access is at `Xn`, and `Xn + imm` is written back to `Xn`.
+    PostIndex,
+}
+
+/// The extension applied to the offset register of a register offset load/store.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum RegExtend {
+    /// `UXTW`, the low 32 bits of `Xm` zero-extended.
+    Uxtw,
+    /// `LSL` (or `UXTX`), `Xm` as is.
+    Lsl,
+    /// `SXTW`, the low 32 bits of `Xm` sign-extended.
+    Sxtw,
+    /// `SXTX`, `Xm` as is.
+    Sxtx,
+}
+
+/// The offset added to the base register of a load/store.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum LoadStoreOffset {
+    /// An immediate offset, already scaled by the access size.
+    Imm(i64),
+    /// A register offset, `extend(Xm) << shift`.
+    Reg {
+        /// The offset register, 31 being `XZR`.
+        rm: usize,
+        /// The extension of `Xm`.
+        extend: RegExtend,
+        /// The left shift applied after the extension.
+        shift: u32,
+    },
+}
+
+/// A decoded A64 load/store of one or two registers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct LoadStore {
+    /// Whether the instruction is a load.
+    pub load: bool,
+    /// Whether the transfer registers are SIMD&FP registers rather than general-purpose ones.
+    pub simd: bool,
+    /// The size of the access to each register in bytes, 1 to 16.
+    pub size: usize,
+    /// The first transfer register, 31 being `XZR` for general-purpose registers.
+    pub rt: usize,
+    /// The second transfer register of a pair.
+    pub rt2: Option<usize>,
+    /// Whether a general-purpose register load is sign-extended.
+    pub sign_ext: bool,
+    /// The width of the general-purpose transfer registers in bytes, 4 for `Wt`, 8 for `Xt`.
+    pub reg_width: usize,
+    /// The base register, 31 being `SP`.
+    pub rn: usize,
+    /// The offset added to the base register.
+    pub offset: LoadStoreOffset,
+    /// How the address is formed and whether the base register is written back.
+    pub mode: AddressMode,
+}
+
+impl LoadStore {
+    /// Returns the offset added to the base register, with `index` the value of the offset
+    /// register for register offset instructions.
+    pub fn offset_value(&self, index: u64) -> u64 {
+        match self.offset {
+            LoadStoreOffset::Imm(imm) => imm as u64,
+            LoadStoreOffset::Reg { extend, shift, .. } => {
+                let value = match extend {
+                    RegExtend::Uxtw => index as u32 as u64,
+                    RegExtend::Sxtw => index as u32 as i32 as i64 as u64,
+                    RegExtend::Lsl | RegExtend::Sxtx => index,
+                };
+                value << shift
+            }
+        }
+    }
+
+    /// Returns the virtual address of the first byte accessed, given the value of the base
+    /// register `base` and of the offset register `index`.
+    pub fn address(&self, base: u64, index: u64) -> u64 {
+        match self.mode {
+            AddressMode::PostIndex => base,
+            AddressMode::Offset | AddressMode::PreIndex => {
+                base.wrapping_add(self.offset_value(index))
+            }
+        }
+    }
+
+    /// Returns the value written back to the base register, if any.
+    pub fn writeback(&self, base: u64, index: u64) -> Option<u64> {
+        match self.mode {
+            AddressMode::Offset => None,
+            AddressMode::PreIndex | AddressMode::PostIndex => {
+                Some(base.wrapping_add(self.offset_value(index)))
+            }
+        }
+    }
+}
+
+/// Sign-extends the lowest `bits` bits of `value`.
+const fn sign_extend(value: u32, bits: u32) -> i64 {
+    let shift = 64 - bits;
+    ((value as i64) << shift) >> shift
+}
+
+/// Decodes the size, direction and extension of a single register load/store from its `size`,
+/// `V` and `opc` fields, as `(size in bytes, load, sign_ext, reg_width)`.
+///
+/// Returns `None` for prefetches and unallocated encodings.
+fn decode_single_size(size: u32, simd: bool, opc: u32) -> Option<(usize, bool, bool, usize)> {
+    if simd {
+        // opc<1> selects the 128-bit Q registers, only valid with size 0.
+        let bytes = match (size, opc >> 1) {
+            (size, 0) => 1 << size,
+            (0, 1) => 16,
+            _ => return None,
+        };
+        return Some((bytes, opc & 1 != 0, false, 8));
+    }
+
+    let bytes = 1 << size;
+    let width = if size == 3 { 8 } else { 4 };
+    match opc {
+        0b00 => Some((bytes, false, false, width)),
+        0b01 => Some((bytes, true, false, width)),
+        // LDRSB, LDRSH and LDRSW to Xt, size 3 being PRFM.
+        0b10 if size < 3 => Some((bytes, true, true, 8)),
+        // LDRSB and LDRSH to Wt.
+        0b11 if size < 2 => Some((bytes, true, true, 4)),
+        _ => None,
+    }
+}
+
+/// Decodes the A64 load/store instruction `insn`.
+///
+/// The supported instructions are the loads and stores of a single register (`LDR`, `STR`,
+/// `LDUR`, `LDTR` and their byte, halfword and sign-extending variants) with an unsigned
+/// immediate, unscaled immediate, pre-index, post-index or register offset, and the loads and
+/// stores of a pair of registers (`LDP`, `STP`, `LDPSW`, `LDNP`, `STNP`), for both the
+/// general-purpose and the SIMD&FP registers.
+///
+/// Returns `None` for other instructions, e.g. exclusive, atomic and SIMD structure loads and
+/// stores, prefetches, and unallocated encodings.
+pub(crate) fn decode_load_store(insn: u32) -> Option<LoadStore> {
+    let rt = (insn & 0x1f) as usize;
+    let rn = ((insn >> 5) & 0x1f) as usize;
+    let simd = insn & (1 << 26) != 0;
+    let size = insn >> 30;
+    let opc = (insn >> 22) & 0b11;
+
+    if insn & 0x3a00_0000 == 0x2800_0000 {
+        return decode_pair(insn, rt, rn, simd);
+    }
+
+    let (bytes, load, sign_ext, reg_width, offset, mode) = if insn & 0x3b00_0000 == 0x3900_0000 {
+        // Unsigned immediate offset, scaled by the access size.
+        let (bytes, load, sign_ext, reg_width) = decode_single_size(size, simd, opc)?;
+        let imm12 = ((insn >> 10) & 0xfff) as i64;
+        let offset = LoadStoreOffset::Imm(imm12 << bytes.trailing_zeros());
+        (
+            bytes,
+            load,
+            sign_ext,
+            reg_width,
+            offset,
+            AddressMode::Offset,
+        )
+    } else if insn & 0x3b20_0000 == 0x3800_0000 {
+        // Unscaled immediate, post-index, unprivileged and pre-index, by bits [11:10].
+        let (bytes, load, sign_ext, reg_width) = decode_single_size(size, simd, opc)?;
+        let mode = match (insn >> 10) & 0b11 {
+            0b00 => AddressMode::Offset,
+            0b01 => AddressMode::PostIndex,
+            // There are no unprivileged SIMD&FP loads and stores.
+            0b10 if !simd => AddressMode::Offset,
+            0b11 => AddressMode::PreIndex,
+            _ => return None,
+        };
+        let offset = LoadStoreOffset::Imm(sign_extend((insn >> 12) & 0x1ff, 9));
+        (bytes, load, sign_ext, reg_width, offset, mode)
+    } else if insn & 0x3b20_0c00 == 0x3820_0800 {
+        // Register offset.
+        let (bytes, load, sign_ext, reg_width) = decode_single_size(size, simd, opc)?;
+        let extend = match (insn >> 13) & 0b111 {
+            0b010 => RegExtend::Uxtw,
+            0b011 => RegExtend::Lsl,
+            0b110 => RegExtend::Sxtw,
+            0b111 => RegExtend::Sxtx,
+            _ => return None,
+        };
+        let shift = if insn & (1 << 12) != 0 {
+            bytes.trailing_zeros()
+        } else {
+            0
+        };
+        let offset = LoadStoreOffset::Reg {
+            rm: ((insn >> 16) & 0x1f) as usize,
+            extend,
+            shift,
+        };
+        (
+            bytes,
+            load,
+            sign_ext,
+            reg_width,
+            offset,
+            AddressMode::Offset,
+        )
+    } else {
+        return None;
+    };
+
+    Some(LoadStore {
+        load,
+        simd,
+        size: bytes,
+        rt,
+        rt2: None,
+        sign_ext,
+        reg_width,
+        rn,
+        offset,
+        mode,
+    })
+}
+
+/// Decodes a load/store pair instruction, see [`decode_load_store`].
+fn decode_pair(insn: u32, rt: usize, rn: usize, simd: bool) -> Option<LoadStore> {
+    let opc = insn >> 30;
+    let load = insn & (1 << 22) != 0;
+    let index = (insn >> 23) & 0b11;
+
+    let (bytes, sign_ext, reg_width) = match (simd, opc, load) {
+        (true, 0b00, _) => (4, false, 8),
+        (true, 0b01, _) => (8, false, 8),
+        (true, 0b10, _) => (16, false, 8),
+        (false, 0b00, _) => (4, false, 4),
+        (false, 0b10, _) => (8, false, 8),
+        // LDPSW, which has no non-temporal variant.
+        (false, 0b01, true) if index != 0b00 => (4, true, 8),
+        // STGP and unallocated encodings.
+        _ => return None,
+    };
+    let mode = match index {
+        // LDNP and STNP, only differing in the cache allocation hint.
+        0b00 | 0b10 => AddressMode::Offset,
+        0b01 => AddressMode::PostIndex,
+        _ => AddressMode::PreIndex,
+    };
+    let imm7 = sign_extend((insn >> 15) & 0x7f, 7);
+
+    Some(LoadStore {
+        load,
+        simd,
+        size: bytes,
+        rt,
+        rt2: Some(((insn >> 10) & 0x1f) as usize),
+        sign_ext,
+        reg_width,
+        rn,
+        offset: LoadStoreOffset::Imm(imm7 << bytes.trailing_zeros()),
+        mode,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns a 64-bit general-purpose register store at `[Xn, offset]`, to be overridden by
+    /// the expected fields.
+    const fn base(rt: usize, rn: usize, offset: LoadStoreOffset) -> LoadStore {
+        LoadStore {
+            load: false,
+            simd: false,
+            size: 8,
+            rt,
+            rt2: None,
+            sign_ext: false,
+            reg_width: 8,
+            rn,
+            offset,
+            mode: AddressMode::Offset,
+        }
+    }
+
+    const fn reg(rm: usize, extend: RegExtend, shift: u32) -> LoadStoreOffset {
+        LoadStoreOffset::Reg { rm, extend, shift }
+    }
+
+    #[test]
+    fn unsigned_immediate() {
+        // ldr x1, [x2, #16]
+        assert_eq!(
+            decode_load_store(0xf940_0841),
+            Some(LoadStore {
+                load: true,
+                ..base(1, 2, LoadStoreOffset::Imm(16))
+            })
+        );
+        // strb w3, [x4, #4095]
+        assert_eq!(
+            decode_load_store(0x393f_fc83),
+            Some(LoadStore {
+                size: 1,
+                reg_width: 4,
+                ..base(3, 4, LoadStoreOffset::Imm(4095))
+            })
+        );
+        // ldrsh x5, [x6, #2]
+        assert_eq!(
+            decode_load_store(0x7980_04c5),
+            Some(LoadStore {
+                load: true,
+                size: 2,
+                sign_ext: true,
+                ..base(5, 6, LoadStoreOffset::Imm(2))
+            })
+        );
+        // ldrsb w7, [x8]
+        assert_eq!(
+            decode_load_store(0x39c0_0107),
+            Some(LoadStore {
+                load: true,
+                size: 1,
+                sign_ext: true,
+                reg_width: 4,
+                ..base(7, 8, LoadStoreOffset::Imm(0))
+            })
+        );
+    }
+
+    #[test]
+    fn unscaled_and_indexed_immediate() {
+        // ldur x1, [x2, #-8]
+        assert_eq!(
+            decode_load_store(0xf85f_8041),
+            Some(LoadStore {
+                load: true,
+                ..base(1, 2, LoadStoreOffset::Imm(-8))
+            })
+        );
+        // ldtr x6, [x7, #8]
+        assert_eq!(
+            decode_load_store(0xf840_88e6),
+            Some(LoadStore {
+                load: true,
+                ..base(6, 7, LoadStoreOffset::Imm(8))
+            })
+        );
+
+        // ldr w3, [x4], #-4
+        let access = decode_load_store(0xb85f_c483).unwrap();
+        assert_eq!(
+            access,
+            LoadStore {
+                load: true,
+                size: 4,
+                reg_width: 4,
+                mode: AddressMode::PostIndex,
+                ..base(3, 4, LoadStoreOffset::Imm(-4))
+            }
+        );
+        assert_eq!(access.address(0x1000, 0), 0x1000);
+        assert_eq!(access.writeback(0x1000, 0), Some(0xffc));
+
+        // str x5, [sp, #-16]!
+        let access = decode_load_store(0xf81f_0fe5).unwrap();
+        assert_eq!(
+            access,
+            LoadStore {
+                mode: AddressMode::PreIndex,
+                ..base(5, 31, LoadStoreOffset::Imm(-16))
+            }
+        );
+        assert_eq!(access.address(0x1000, 0), 0xff0);
+        assert_eq!(access.writeback(0x1000, 0), Some(0xff0));
+    }
+
+    #[test]
+    fn register_offset() {
+        // ldr x1, [x2, x3, lsl #3]
+        let access = decode_load_store(0xf863_7841).unwrap();
+        assert_eq!(
+            access,
+            LoadStore {
+                load: true,
+                ..base(1, 2, reg(3, RegExtend::Lsl, 3))
+            }
+        );
+        assert_eq!(access.offset_value(2), 16);
+        assert_eq!(access.address(0x1000, 2), 0x1010);
+        assert_eq!(access.writeback(0x1000, 2), None);
+
+        // ldr w1, [x2, w3, uxtw]
+        let access = decode_load_store(0xb863_4841).unwrap();
+        assert_eq!(
+            access,
+            LoadStore {
+                load: true,
+                size: 4,
+                reg_width: 4,
+                ..base(1, 2, reg(3, RegExtend::Uxtw, 0))
+            }
+        );
+        assert_eq!(access.offset_value(0xffff_ffff_0000_0010), 0x10);
+        assert_eq!(access.offset_value(0xffff_fffc), 0xffff_fffc);
+
+        // ldrh w1, [x2, w3, sxtw #1]
+        let access = decode_load_store(0x7863_d841).unwrap();
+        assert_eq!(
+            access,
+            LoadStore {
+                load: true,
+                size: 2,
+                reg_width: 4,
+                ..base(1, 2, reg(3, RegExtend::Sxtw, 1))
+            }
+        );
+        assert_eq!(access.offset_value(0xffff_fffe), -4i64 as u64);
+        assert_eq!(access.address(0x1000, 0xffff_fffe), 0xffc);
+
+        // strb w1, [x2, x3, sxtx]
+        assert_eq!(
+            decode_load_store(0x3823_e841),
+            Some(LoadStore {
+                size: 1,
+                reg_width: 4,
+                ..base(1, 2, reg(3, RegExtend::Sxtx, 0))
+            })
+        );
+        // ldrsw x1, [x2, x3]
+        assert_eq!(
+            decode_load_store(0xb8a3_6841),
+            Some(LoadStore {
+                load: true,
+                size: 4,
+                sign_ext: true,
+                ..base(1, 2, reg(3, RegExtend::Lsl, 0))
+            })
+        );
+    }
+
+    #[test]
+    fn pairs() {
+        // ldp x1, x2, [sp, #-32]!
+        assert_eq!(
+            decode_load_store(0xa9fe_0be1),
+            Some(LoadStore {
+                load: true,
+                rt2: Some(2),
+                mode: AddressMode::PreIndex,
+                ..base(1, 31, LoadStoreOffset::Imm(-32))
+            })
+        );
+        // stp w3, w4, [x5], #8
+        assert_eq!(
+            decode_load_store(0x2881_10a3),
+            Some(LoadStore {
+                size: 4,
+                rt2: Some(4),
+                reg_width: 4,
+                mode: AddressMode::PostIndex,
+                ..base(3, 5, LoadStoreOffset::Imm(8))
+            })
+        );
+        // ldpsw x6, x7, [x8, #-8]
+        assert_eq!(
+            decode_load_store(0x697f_1d06),
+            Some(LoadStore {
+                load: true,
+                size: 4,
+                rt2: Some(7),
+                sign_ext: true,
+                ..base(6, 8, LoadStoreOffset::Imm(-8))
+            })
+        );
+        // ldnp x9, x10, [x11, #16]
+        assert_eq!(
+            decode_load_store(0xa841_2969),
+            Some(LoadStore {
+                load: true,
+                rt2: Some(10),
+                ..base(9, 11, LoadStoreOffset::Imm(16))
+            })
+        );
+    }
+
+    #[test]
+    fn simd_and_fp() {
+        // ldr q0, [x1, #32]
+        assert_eq!(
+            decode_load_store(0x3dc0_0820),
+            Some(LoadStore {
+                load: true,
+                simd: true,
+                size: 16,
+                ..base(0, 1, LoadStoreOffset::Imm(32))
+            })
+        );
+        // str d1, [x2], #8
+        assert_eq!(
+            decode_load_store(0xfc00_8441),
+            Some(LoadStore {
+                simd: true,
+                mode: AddressMode::PostIndex,
+                ..base(1, 2, LoadStoreOffset::Imm(8))
+            })
+        );
+        // ldr s3, [x4, x5, lsl #2]
+        assert_eq!(
+            decode_load_store(0xbc65_7883),
+            Some(LoadStore {
+                load: true,
+                simd: true,
+                size: 4,
+                ..base(3, 4, reg(5, RegExtend::Lsl, 2))
+            })
+        );
+        // ldur q4, [x5, #-1]
+        assert_eq!(
+            decode_load_store(0x3cdf_f0a4),
+            Some(LoadStore {
+                load: true,
+                simd: true,
+                size: 16,
+                ..base(4, 5, LoadStoreOffset::Imm(-1))
+            })
+        );
+        // ldr b6, [x7, #1]
+        assert_eq!(
+            decode_load_store(0x3d40_04e6),
+            Some(LoadStore {
+                load: true,
+                simd: true,
+                size: 1,
+                ..base(6, 7, LoadStoreOffset::Imm(1))
+            })
+        );
+    }
+
+    #[test]
+    fn simd_and_fp_pairs() {
+        // stnp q0, q1, [x2, #32]
+        assert_eq!(
+            decode_load_store(0xac01_0440),
+            Some(LoadStore {
+                simd: true,
+                size: 16,
+                rt2: Some(1),
+                ..base(0, 2, LoadStoreOffset::Imm(32))
+            })
+        );
+        // ldp d2, d3, [x4]
+        assert_eq!(
+            decode_load_store(0x6d40_0c82),
+            Some(LoadStore {
+                load: true,
+                simd: true,
+                rt2: Some(3),
+                ..base(2, 4, LoadStoreOffset::Imm(0))
+            })
+        );
+        // stp s5, s6, [x7, #-4]!
+        assert_eq!(
+            decode_load_store(0x2dbf_98e5),
+            Some(LoadStore {
+                simd: true,
+                size: 4,
+                rt2: Some(6),
+                mode: AddressMode::PreIndex,
+                ..base(5, 7, LoadStoreOffset::Imm(-4))
+            })
+        );
+    }
+
+    #[test]
+    fn rejected() {
+        // prfm pldl1keep, [x1, #8]
+        assert_eq!(decode_load_store(0xf980_0420), None);
+        // stgp x1, x2, [x3]
+        assert_eq!(decode_load_store(0x6900_0861), None);
+        // ldtr x6, [x7, #8] with the SIMD&FP bit set, there is no unprivileged SIMD&FP load.
+        assert_eq!(decode_load_store(0xfc40_88e6), None);
+        // ldr x1, [x2, x3, lsl #3] with the reserved extend option 0b000.
+        assert_eq!(decode_load_store(0xf863_1841), None);
+        // ldpsw x6, x7, [x8, #-8] as a non-temporal pair, which does not exist.
+        assert_eq!(decode_load_store(0x687f_1d06), None);
+        // ldxr x0, [x1] and nop.
+        assert_eq!(decode_load_store(0xc85f_7c20), None);
+        assert_eq!(decode_load_store(0xd503_201f), None);
+    }
+}
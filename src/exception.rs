@@ -13,22 +13,37 @@
 // limitations under the License.
 
 use crate::TrapFrame;
+use crate::decode::{LoadStoreOffset, decode_load_store};
 use crate::exception_utils::{
-    exception_class, exception_class_value, exception_data_abort_access_is_write,
-    exception_data_abort_access_reg, exception_data_abort_access_reg_width,
-    exception_data_abort_access_width, exception_data_abort_handleable,
-    exception_data_abort_is_permission_fault, exception_data_abort_is_translate_fault,
-    exception_esr, exception_fault_addr, exception_next_instruction_step, exception_sysreg_addr,
-    exception_sysreg_direction_write, exception_sysreg_gpr,
+    ESR_EC_HVC32, ESR_EC_SMC32, ESR_EC_VMRS_ID, aarch32_it_advance,
+    exception_aarch32_condition_passed, exception_abort_fault_level,
+    exception_abort_is_access_flag_fault, exception_abort_is_s1ptw, exception_class,
+    exception_class_value, exception_cp15_64_addr, exception_cp15_addr,
+    exception_data_abort_access_is_acquire_release, exception_data_abort_access_is_sign_ext,
+    exception_data_abort_access_is_write, exception_data_abort_access_reg,
+    exception_data_abort_access_reg_width, exception_data_abort_access_width,
+    exception_data_abort_handleable, exception_data_abort_is_permission_fault,
+    exception_data_abort_is_translate_fault, exception_esr, exception_fault_addr, exception_hpfar,
+    exception_next_instruction_step, exception_serror_severity, exception_sysreg_addr,
+    exception_sysreg_direction_write, exception_sysreg_gpr, exception_sysreg_gpr2,
+    exception_wfx_is_wfe, translate_guest_va, translate_guest_va_to_pa,
 };
+use crate::exit::{Aarch64ExitDetail, FaultAccess, Stage2FaultKind};
+use crate::id_regs::IdRegisters;
+use crate::smc::{
+    SMCCC_ARCH_FEATURES, SMCCC_ARCH_SOC_ID, SMCCC_ARCH_WORKAROUND_1, SMCCC_ARCH_WORKAROUND_2,
+    SMCCC_ARCH_WORKAROUND_3, SMCCC_VERSION, SmcFallback, WorkaroundState, host_workarounds,
+};
+use crate::vcpu::{Aarch64VCpu, MmioEmulation, PendingMmioRead};
 
-use aarch64_cpu::registers::{ESR_EL2, HCR_EL2, Readable, SCTLR_EL1, VTCR_EL2, VTTBR_EL2};
+use aarch64_cpu::registers::{ESR_EL2, FAR_EL2, HCR_EL2, Readable, SCTLR_EL1, VTCR_EL2, VTTBR_EL2};
 use axaddrspace::{
-    GuestPhysAddr,
+    GuestPhysAddr, MappingFlags,
     device::{AccessWidth, SysRegAddr},
 };
 use axerrno::{AxError, AxResult};
-use axvcpu::AxVCpuExitReason;
+use axvcpu::{AxVCpuExitReason, AxVCpuHal};
+use axvisor_api::memory::{PhysAddr, phys_to_virt};
 use log::error;
 
 numeric_enum_macro::numeric_enum! {
@@ -46,6 +61,10 @@ pub enum TrapKind {
 const EXCEPTION_SYNC: usize = TrapKind::Synchronous as usize;
 /// Equals to [`TrapKind::Irq`], used in exception.S.
 const EXCEPTION_IRQ: usize = TrapKind::Irq as usize;
+/// Equals to [`TrapKind::Fiq`], used in exception.S.
+const EXCEPTION_FIQ: usize = TrapKind::Fiq as usize;
+/// Equals to [`TrapKind::SError`], used in exception.S.
+const EXCEPTION_SERROR: usize = TrapKind::SError as usize;
 
 #[repr(u8)]
 #[derive(Debug)]
@@ -61,6 +80,8 @@ core::arch::global_asm!(
     include_str!("exception.S"),
     exception_sync = const EXCEPTION_SYNC,
     exception_irq = const EXCEPTION_IRQ,
+    exception_fiq = const EXCEPTION_FIQ,
+    exception_serror = const EXCEPTION_SERROR,
 );
 
 /// Handles synchronous exceptions that occur during the execution of a guest VM.
@@ -68,87 +89,242 @@ core::arch::global_asm!(
 /// This function examines the exception class (EC) to determine the cause of the exception
 /// and then handles it accordingly.
 ///
-/// Currently we just handle exception type including data abort (`DataAbortLowerEL`) and hypervisor call (`HVC64)`.
+/// Currently we just handle exception type including data abort (`DataAbortLowerEL`), hypervisor call (`HVC64)`,
+/// trapped WFI/WFE (`TrappedWFIorWFE`) and trapped FP/SIMD or SVE accesses (`TrappedFP`, `TrappedSve`).
+///
+/// For AArch32 guests, `HVC32`, `SMC32` and MCR/MRC accesses to CP15 (`TrappedMCRorMRC`) are handled
+/// as well. Trapped AArch32 instructions that fail their condition code check are skipped.
 ///
 /// # Arguments
 ///
-/// * `ctx` - A mutable reference to the `TrapFrame`, which contains the saved state of the guest VM's CPU registers at the time of the exception.
+/// * `vcpu` - A mutable reference to the `Aarch64VCpu` that trapped, whose `ctx` contains the saved state of
+///   the guest VM's CPU registers at the time of the exception.
 ///
 /// # Returns
 ///
 /// An `AxResult` containing an `AxVCpuExitReason` indicating the reason for the VM exit.
 /// This could be due to a hypervisor call (`Hypercall`) or other reasons such as data aborts.
 ///
-/// # Panics
-///
-/// If an unhandled exception class is encountered, the function will panic, outputting
-/// details about the exception including the instruction pointer, faulting address, exception
-/// syndrome register (ESR), and system control registers.
-///
-pub fn handle_exception_sync(ctx: &mut TrapFrame) -> AxResult<AxVCpuExitReason> {
+/// If an unhandled exception class is encountered, the exception is reported as
+/// [`AxVCpuExitReason::FailEntry`], see [`fail_entry`].
+pub fn handle_exception_sync<H: AxVCpuHal>(
+    vcpu: &mut Aarch64VCpu<H>,
+) -> AxResult<AxVCpuExitReason> {
+    let ctx = &mut vcpu.ctx;
+
+    if ctx.is_aarch32() && !exception_aarch32_condition_passed(ctx.spsr) {
+        skip_trapped_instruction(ctx);
+        return Ok(AxVCpuExitReason::Nothing);
+    }
+
     match exception_class() {
-        Some(ESR_EL2::EC::Value::DataAbortLowerEL) => {
-            let elr = ctx.exception_pc();
-            let val = elr + exception_next_instruction_step();
-            ctx.set_exception_pc(val);
-            handle_data_abort(ctx)
+        Some(ESR_EL2::EC::Value::DataAbortLowerEL) => handle_data_abort(vcpu),
+        Some(ESR_EL2::EC::Value::InstrAbortLowerEL) => handle_instruction_abort(vcpu),
+        Some(ESR_EL2::EC::Value::HVC64) => handle_hvc_exception(vcpu),
+        Some(ESR_EL2::EC::Value::TrappedWFIorWFE) => {
+            skip_trapped_instruction(ctx);
+            if exception_wfx_is_wfe() {
+                // WFE waits for an event rather than an interrupt, just give the hypervisor
+                // a chance to yield.
+                Ok(AxVCpuExitReason::Nothing)
+            } else {
+                vcpu.exit_detail = Aarch64ExitDetail::Halt {
+                    timer_deadline: vcpu.timer_next_deadline(),
+                };
+                Ok(AxVCpuExitReason::Halt)
+            }
+        }
+        Some(ESR_EL2::EC::Value::TrappedMsrMrs) => handle_system_register(vcpu),
+        Some(ESR_EL2::EC::Value::TrappedMCRorMRC) => handle_cp15_access(vcpu, 0),
+        Some(ESR_EL2::EC::Value::TrappedMCRorMRC2) => handle_cp15_access(vcpu, 2),
+        Some(ESR_EL2::EC::Value::TrappedMCRRorMRRC) => handle_cp15_64_access(vcpu),
+        Some(ESR_EL2::EC::Value::TrappedFP) => {
+            // The guest accessed FP/SIMD registers for the first time in this run,
+            // switch them lazily and let the guest retry the access.
+            unsafe { vcpu.load_guest_fp_regs() };
+            Ok(AxVCpuExitReason::Nothing)
         }
-        Some(ESR_EL2::EC::Value::HVC64) => {
-            // The `#imm`` argument when triggering a hvc call, currently not used.
-            let _hvc_arg_imm16 = ESR_EL2.read(ESR_EL2::ISS);
-
-            // Is this a psci call?
-            //
-            // By convention, a psci call can use either the `hvc` or the `smc` instruction.
-            // NimbOS uses `hvc`, `ArceOS` use `hvc` too when running on QEMU.
-            if let Some(result) = handle_psci_call(ctx) {
-                return result;
+        Some(ESR_EL2::EC::Value::TrappedSve) => {
+            if vcpu.sve_vl == 0 {
+                // SVE instructions are undefined for the guest, as if SVE was not implemented.
+                debug!(
+                    "SVE access from a vCPU without SVE @pc {:#x}, esr {:#x}",
+                    ctx.exception_pc(),
+                    exception_esr()
+                );
+                vcpu.inject_undefined();
+                return Ok(AxVCpuExitReason::Nothing);
             }
 
-            // We assume that guest VM triggers HVC through a `hvc #0`` instruction.
-            // And arm64 hcall implementation uses `x0` to specify the hcall number.
-            // For more details on the hypervisor call (HVC) mechanism and the use of general-purpose registers,
-            // refer to the [Linux Kernel documentation on KVM ARM hypervisor ABI](https://github.com/torvalds/linux/blob/master/Documentation/virt/kvm/arm/hyp-abi.rst).
-            Ok(AxVCpuExitReason::Hypercall {
-                nr: ctx.gpr[0],
-                args: [
-                    ctx.gpr[1], ctx.gpr[2], ctx.gpr[3], ctx.gpr[4], ctx.gpr[5], ctx.gpr[6],
-                ],
-            })
+            // Same as above, SVE registers are switched together with FP/SIMD registers.
+            unsafe { vcpu.load_guest_fp_regs() };
+            Ok(AxVCpuExitReason::Nothing)
         }
-        Some(ESR_EL2::EC::Value::TrappedMsrMrs) => handle_system_register(ctx),
         Some(ESR_EL2::EC::Value::SMC64) => {
-            let elr = ctx.exception_pc();
-            let val = elr + exception_next_instruction_step();
-            ctx.set_exception_pc(val);
-            handle_smc64_exception(ctx)
+            skip_trapped_instruction(ctx);
+            handle_smc64_exception(vcpu)
         }
-        _ => {
-            panic!(
-                "handler not presents for EC_{} @ipa 0x{:x}, @pc 0x{:x}, @esr 0x{:x},
-                @sctlr_el1 0x{:x}, @vttbr_el2 0x{:x}, @vtcr_el2: {:#x} hcr: {:#x} ctx:{}",
-                exception_class_value(),
-                exception_fault_addr()?,
-                (*ctx).exception_pc(),
-                exception_esr(),
-                SCTLR_EL1.get() as usize,
-                VTTBR_EL2.get() as usize,
-                VTCR_EL2.get() as usize,
-                HCR_EL2.get() as usize,
-                ctx
-            );
+        None if exception_class_value() == ESR_EC_VMRS_ID => handle_vmrs_id_access(vcpu),
+        None if exception_class_value() == ESR_EC_HVC32 => handle_hvc_exception(vcpu),
+        None if exception_class_value() == ESR_EC_SMC32 => {
+            skip_trapped_instruction(ctx);
+            handle_smc64_exception(vcpu)
         }
+        _ => fail_entry(vcpu, "Unhandled exception"),
     }
 }
 
-fn handle_data_abort(context_frame: &mut TrapFrame) -> AxResult<AxVCpuExitReason> {
+/// Reports a guest exception that can not be handled as [`AxVCpuExitReason::FailEntry`].
+///
+/// The syndrome of the exception is recorded as [`Aarch64ExitDetail::FailEntry`], so that the
+/// VMM can stop or reset the offending VM instead of bringing down the whole hypervisor.
+pub fn fail_entry<H: AxVCpuHal>(
+    vcpu: &mut Aarch64VCpu<H>,
+    reason: &str,
+) -> AxResult<AxVCpuExitReason> {
+    let esr = exception_esr() as u64;
+    let ec = exception_class_value() as u8;
+    let far = FAR_EL2.get();
+    let hpfar = exception_hpfar() as u64;
+    let elr = vcpu.ctx.exception_pc() as u64;
+
+    error!(
+        "{} for EC_{:#x} @pc {:#x}, @esr {:#x}, @far {:#x}, @hpfar {:#x},
+        @sctlr_el1 {:#x}, @vttbr_el2 {:#x}, @vtcr_el2: {:#x} hcr: {:#x} ctx:{}",
+        reason,
+        ec,
+        elr,
+        esr,
+        far,
+        hpfar,
+        SCTLR_EL1.get() as usize,
+        VTTBR_EL2.get() as usize,
+        VTCR_EL2.get() as usize,
+        HCR_EL2.get() as usize,
+        vcpu.ctx
+    );
+
+    vcpu.exit_detail = Aarch64ExitDetail::FailEntry {
+        esr,
+        ec,
+        far,
+        hpfar,
+        elr,
+    };
+    Ok(AxVCpuExitReason::FailEntry {
+        hardware_entry_failure_reason: esr,
+    })
+}
+
+/// Handles SErrors (asynchronous aborts) taken from a guest VM.
+///
+/// The SError is classified according to `ESR_EL2.ISS.{IDS, AET, DFSC}` and reported to the VMM
+/// as [`Aarch64ExitDetail::SError`], leaving the guest state untouched. Fatal errors are reported
+/// as [`AxVCpuExitReason::FailEntry`], so that the VM is not resumed.
+pub fn handle_serror<H: AxVCpuHal>(vcpu: &mut Aarch64VCpu<H>) -> AxResult<AxVCpuExitReason> {
+    let esr = exception_esr() as u64;
+    let severity = exception_serror_severity();
+    warn!(
+        "SError from guest @pc {:#x}, esr {:#x}, severity {:?}",
+        vcpu.ctx.exception_pc(),
+        esr,
+        severity
+    );
+
+    vcpu.exit_detail = Aarch64ExitDetail::SError { esr, severity };
+    if severity.is_fatal() {
+        return Ok(AxVCpuExitReason::FailEntry {
+            hardware_entry_failure_reason: esr,
+        });
+    }
+    Ok(AxVCpuExitReason::Nothing)
+}
+
+/// Advances the guest PC past the trapped instruction.
+///
+/// For guests in AArch32 T32 state, the IT state is advanced as well.
+fn skip_trapped_instruction(ctx: &mut TrapFrame) {
+    let elr = ctx.exception_pc();
+    let val = elr + exception_next_instruction_step();
+    ctx.set_exception_pc(val);
+    if ctx.is_aarch32() {
+        ctx.spsr = aarch32_it_advance(ctx.spsr);
+    }
+}
+
+/// Advances the guest PC past the trapped instruction when the guest is resumed, unless the
+/// VMM asks for it to be retried with [`Aarch64VCpu::retry_instruction`].
+///
+/// Used for the accesses emulated by the VMM, so that the guest PC is left untouched if the
+/// emulation fails.
+fn defer_skip_trapped_instruction<H: AxVCpuHal>(vcpu: &mut Aarch64VCpu<H>) {
+    vcpu.pending_pc_step = exception_next_instruction_step();
+}
+
+/// Handles HVC exceptions, from both AArch64 (`HVC64`) and AArch32 (`HVC32`).
+///
+/// The preferred return address of an HVC exception is the instruction after `hvc`, so there is no
+/// need to advance the guest PC.
+fn handle_hvc_exception<H: AxVCpuHal>(vcpu: &mut Aarch64VCpu<H>) -> AxResult<AxVCpuExitReason> {
+    // The `#imm`` argument when triggering a hvc call, currently not used.
+    let _hvc_arg_imm16 = ESR_EL2.read(ESR_EL2::ISS);
+
+    // Is this a psci call?
+    //
+    // By convention, a psci call can use either the `hvc` or the `smc` instruction.
+    // NimbOS uses `hvc`, `ArceOS` use `hvc` too when running on QEMU.
+    if let Some(result) = handle_psci_call(vcpu) {
+        return result;
+    }
+    if let Some(result) = handle_smccc_arch_call(vcpu) {
+        return result;
+    }
+    let ctx = &vcpu.ctx;
+
+    // We assume that guest VM triggers HVC through a `hvc #0`` instruction.
+    // And arm64 hcall implementation uses `x0` to specify the hcall number.
+    // For more details on the hypervisor call (HVC) mechanism and the use of general-purpose registers,
+    // refer to the [Linux Kernel documentation on KVM ARM hypervisor ABI](https://github.com/torvalds/linux/blob/master/Documentation/virt/kvm/arm/hyp-abi.rst).
+    Ok(AxVCpuExitReason::Hypercall {
+        nr: ctx.gpr[0],
+        args: [
+            ctx.gpr[1], ctx.gpr[2], ctx.gpr[3], ctx.gpr[4], ctx.gpr[5], ctx.gpr[6],
+        ],
+    })
+}
+
+fn handle_data_abort<H: AxVCpuHal>(vcpu: &mut Aarch64VCpu<H>) -> AxResult<AxVCpuExitReason> {
+    // These are not MMIO accesses, but mappings for the VMM to fix up, which do not need
+    // the instruction syndrome.
+    if exception_abort_is_s1ptw()
+        || exception_data_abort_is_permission_fault()
+        || exception_abort_is_access_flag_fault()
+    {
+        let access = if exception_abort_is_s1ptw() || exception_data_abort_access_is_write() {
+            FaultAccess::Write
+        } else {
+            FaultAccess::Read
+        };
+        return handle_stage2_fault(vcpu, access);
+    }
+
+    if !exception_data_abort_is_translate_fault() {
+        return fail_entry(vcpu, "Data abort is not a translation fault");
+    }
+
+    if !exception_data_abort_handleable() {
+        return handle_data_abort_without_syndrome(vcpu);
+    }
+
     let addr = exception_fault_addr()?;
     let access_width = exception_data_abort_access_width();
     let is_write = exception_data_abort_access_is_write();
-    //let sign_ext = exception_data_abort_access_is_sign_ext();
+    let sign_ext = exception_data_abort_access_is_sign_ext();
+    let acquire_release = exception_data_abort_access_is_acquire_release();
     let reg = exception_data_abort_access_reg();
     let reg_width = exception_data_abort_access_reg_width();
 
+    let context_frame = &mut vcpu.ctx;
     trace!(
         "Data fault @{:?}, ELR {:#x}, esr: 0x{:x}",
         addr,
@@ -166,62 +342,184 @@ fn handle_data_abort(context_frame: &mut TrapFrame) -> AxResult<AxVCpuExitReason
         Err(_) => return Err(AxError::InvalidInput),
     };
 
-    if !exception_data_abort_handleable() {
-        panic!(
-            "Core data abort not handleable {:#x}, esr {:#x}",
-            addr,
-            exception_esr()
-        );
-    }
-
-    if !exception_data_abort_is_translate_fault() {
-        if exception_data_abort_is_permission_fault() {
-            return Err(AxError::Unsupported);
-        } else {
-            panic!("Core data abort is not translate fault {:#x}", addr,);
-        }
-    }
+    let data = vcpu.mmio_data_swap(vcpu.ctx.gpr(reg) as u64, width);
+    defer_skip_trapped_instruction(vcpu);
+    vcpu.exit_detail = Aarch64ExitDetail::Mmio { acquire_release };
 
     if is_write {
-        return Ok(AxVCpuExitReason::MmioWrite {
-            addr,
-            width,
-            data: context_frame.gpr(reg) as u64,
-        });
+        return Ok(AxVCpuExitReason::MmioWrite { addr, width, data });
     }
+    vcpu.pending_mmio_read = Some(PendingMmioRead {
+        reg,
+        width,
+        reg_width,
+        sign_ext,
+        fp_offset: None,
+        fp_half: false,
+    });
     Ok(AxVCpuExitReason::MmioRead {
         addr,
         width,
         reg,
         reg_width,
-        signed_ext: false,
+        signed_ext: sign_ext,
     })
 }
 
+/// Handles a data abort on MMIO without a valid instruction syndrome, by fetching and decoding
+/// the faulting instruction, see [`decode_load_store`].
+///
+/// The access is split into one MMIO exit for each register, or for each half of a 128-bit
+/// SIMD&FP register. The first one is returned here, the others by the following calls of `run`
+/// without entering the guest, see [`Aarch64VCpu::step_mmio_emulation`]. The IPA of each access
+/// is found with the stage 1 translation of the guest, so all of them are expected to target
+/// MMIO.
+fn handle_data_abort_without_syndrome<H: AxVCpuHal>(
+    vcpu: &mut Aarch64VCpu<H>,
+) -> AxResult<AxVCpuExitReason> {
+    if vcpu.ctx.is_aarch32() {
+        return fail_entry(
+            vcpu,
+            "AArch32 data abort without a valid instruction syndrome",
+        );
+    }
+
+    // The guest may have changed its mappings on another vCPU since the abort, in which case
+    // the instruction is just executed again.
+    let Ok(pa) = translate_guest_va_to_pa(vcpu.ctx.exception_pc()) else {
+        return Ok(AxVCpuExitReason::Nothing);
+    };
+    let insn = unsafe {
+        phys_to_virt(PhysAddr::from_usize(pa))
+            .as_ptr_of::<u32>()
+            .read_volatile()
+    };
+    // A64 instructions are always little-endian.
+    let Some(access) = decode_load_store(u32::from_le(insn)) else {
+        return fail_entry(vcpu, "Data abort without a valid instruction syndrome");
+    };
+
+    let base = vcpu.guest_base_register(access.rn);
+    let index = match access.offset {
+        LoadStoreOffset::Reg { rm, .. } => vcpu.ctx.gpr(rm) as u64,
+        LoadStoreOffset::Imm(_) => 0,
+    };
+    let va = access.address(base, index) as usize;
+    trace!(
+        "Data fault without syndrome @{:#x}, ELR {:#x}, insn: {:#010x}",
+        va,
+        vcpu.ctx.exception_pc(),
+        insn,
+    );
+
+    let mut emulation = MmioEmulation {
+        access,
+        addrs: [GuestPhysAddr::from(0); 4],
+        next: 0,
+        writeback: access.writeback(base, index),
+    };
+    for i in 0..emulation.count() {
+        let Ok(addr) = translate_guest_va(va.wrapping_add(i * emulation.width())) else {
+            return Ok(AxVCpuExitReason::Nothing);
+        };
+        emulation.addrs[i] = addr;
+    }
+
+    vcpu.mmio_emulation = Some(emulation);
+    Ok(vcpu
+        .step_mmio_emulation()
+        .unwrap_or(AxVCpuExitReason::Nothing))
+}
+
+/// Handles an instruction abort from a lower EL.
+///
+/// Stage 2 translation, access flag and permission faults on instruction fetches are reported
+/// as stage 2 faults, so that the VMM can map guest memory on demand, e.g. lazily map ROMs.
+/// `IFSC` has the same encoding as `DFSC`, and the IPA is found the same way as for data aborts.
+fn handle_instruction_abort<H: AxVCpuHal>(vcpu: &mut Aarch64VCpu<H>) -> AxResult<AxVCpuExitReason> {
+    if exception_data_abort_is_translate_fault()
+        || exception_abort_is_access_flag_fault()
+        || exception_data_abort_is_permission_fault()
+    {
+        let access = if exception_abort_is_s1ptw() {
+            FaultAccess::Write
+        } else {
+            FaultAccess::Exec
+        };
+        return handle_stage2_fault(vcpu, access);
+    }
+
+    fail_entry(vcpu, "Unhandled instruction abort")
+}
+
+/// Reports a stage 2 fault as [`AxVCpuExitReason::NestedPageFault`], with the details recorded
+/// as [`Aarch64ExitDetail::Stage2Fault`].
+///
+/// The guest PC is not advanced, so the faulting access is retried when the guest resumes.
+fn handle_stage2_fault<H: AxVCpuHal>(
+    vcpu: &mut Aarch64VCpu<H>,
+    access: FaultAccess,
+) -> AxResult<AxVCpuExitReason> {
+    let kind = if exception_data_abort_is_translate_fault() {
+        Stage2FaultKind::Translation
+    } else if exception_abort_is_access_flag_fault() {
+        Stage2FaultKind::AccessFlag
+    } else if exception_data_abort_is_permission_fault() {
+        Stage2FaultKind::Permission
+    } else {
+        return fail_entry(vcpu, "Unhandled stage 2 fault");
+    };
+
+    let Ok(addr) = exception_fault_addr() else {
+        // The IPA of a permission fault is found by translating FAR_EL2 with the guest stage 1
+        // tables, which another vCPU may have changed since, let the guest retry.
+        debug!("Failed to translate the stage 2 fault address, retrying");
+        return Ok(AxVCpuExitReason::Nothing);
+    };
+    let s1ptw = exception_abort_is_s1ptw();
+    let level = exception_abort_fault_level();
+    trace!(
+        "Stage 2 {kind:?} fault @{addr:?}, {access:?}, s1ptw {s1ptw}, level {level}, ELR {:#x}",
+        vcpu.ctx.exception_pc(),
+    );
+
+    vcpu.exit_detail = Aarch64ExitDetail::Stage2Fault {
+        ipa: addr.as_usize() as u64,
+        access,
+        kind,
+        s1ptw,
+        level,
+    };
+    let access_flags = match access {
+        FaultAccess::Read => MappingFlags::READ,
+        FaultAccess::Write => MappingFlags::WRITE,
+        FaultAccess::Exec => MappingFlags::EXECUTE,
+    };
+    Ok(AxVCpuExitReason::NestedPageFault { addr, access_flags })
+}
+
 /// Handles a system register access exception.
 ///
 /// This function processes the exception by reading or writing to a system register
-/// based on the information in the `context_frame`.
+/// based on the information in the trap frame of `vcpu`.
 ///
 /// # Arguments
-/// * `context_frame` - A mutable reference to the trap frame containing the CPU state.
+/// * `vcpu` - The vCPU that trapped, whose PC is advanced when it is resumed.
 ///
 /// # Returns
 /// * `AxResult<AxVCpuExitReason>` - An `AxResult` containing an `AxVCpuExitReason` indicating
 ///   whether the operation was a read or write and the relevant details.
-fn handle_system_register(context_frame: &mut TrapFrame) -> AxResult<AxVCpuExitReason> {
+fn handle_system_register<H: AxVCpuHal>(vcpu: &mut Aarch64VCpu<H>) -> AxResult<AxVCpuExitReason> {
     let iss = ESR_EL2.read(ESR_EL2::ISS);
 
     let addr = exception_sysreg_addr(iss.try_into().unwrap());
-    let elr = context_frame.exception_pc();
-    let val = elr + exception_next_instruction_step();
     let write = exception_sysreg_direction_write(iss);
     let reg = exception_sysreg_gpr(iss) as usize;
-    context_frame.set_exception_pc(val);
+    defer_skip_trapped_instruction(vcpu);
     if write {
         return Ok(AxVCpuExitReason::SysRegWrite {
             addr: SysRegAddr::new(addr),
-            value: context_frame.gpr(reg as usize) as u64,
+            value: vcpu.ctx.gpr(reg) as u64,
         });
     }
     Ok(AxVCpuExitReason::SysRegRead {
@@ -230,63 +528,402 @@ fn handle_system_register(context_frame: &mut TrapFrame) -> AxResult<AxVCpuExitR
     })
 }
 
-/// Handles HVC or SMC exceptions that serve as psci (Power State Coordination Interface) calls.
+/// Handles a trapped MCR or MRC access to a CP15 (or CP14) register from an AArch32 guest.
+///
+/// The access is reported as a system register access, with the address numbered as described
+/// in [`exception_cp15_addr`] and `op0` set to `op0`: 0 for CP15 and 2 for CP14, whose debug
+/// registers have the same encodings as their AArch64 counterparts in `op0` 2. Values written
+/// by MCR are 32-bit.
+///
+/// The `Rt` reported in the ISS is the AArch64 view of the AArch32 register, so it can be used
+/// as the GPR index directly.
+fn handle_cp15_access<H: AxVCpuHal>(
+    vcpu: &mut Aarch64VCpu<H>,
+    op0: usize,
+) -> AxResult<AxVCpuExitReason> {
+    let iss = ESR_EL2.read(ESR_EL2::ISS);
+
+    let addr = exception_cp15_addr(iss as usize) | (op0 << 20);
+    let write = exception_sysreg_direction_write(iss);
+    let reg = exception_sysreg_gpr(iss) as usize;
+    defer_skip_trapped_instruction(vcpu);
+    if write {
+        return Ok(AxVCpuExitReason::SysRegWrite {
+            addr: SysRegAddr::new(addr),
+            value: vcpu.ctx.gpr(reg) as u32 as u64,
+        });
+    }
+    Ok(AxVCpuExitReason::SysRegRead {
+        addr: SysRegAddr::new(addr),
+        reg,
+    })
+}
+
+/// Handles an AArch32 `VMRS` read of `MVFR0`, `MVFR1` or `MVFR2`, trapped by `HCR_EL2.TID3`
+/// with the other ID registers.
 ///
-/// A hvc or smc call with the function in range 0x8000_0000..=0x8000_001F  (when the 32-bit
-/// hvc/smc calling convention is used) or 0xC000_0000..=0xC000_001F (when the 64-bit hvc/smc
-/// calling convention is used) is a psci call. This function handles them all.
-///
-/// Returns `None` if the HVC is not a psci call.
-fn handle_psci_call(ctx: &mut TrapFrame) -> Option<AxResult<AxVCpuExitReason>> {
-    const PSCI_FN_RANGE_32: core::ops::RangeInclusive<u64> = 0x8400_0000..=0x8400_001F;
-    const PSCI_FN_RANGE_64: core::ops::RangeInclusive<u64> = 0xC400_0000..=0xC400_001F;
-
-    const PSCI_FN_VERSION: u64 = 0x0;
-    const _PSCI_FN_CPU_SUSPEND: u64 = 0x1;
-    const PSCI_FN_CPU_OFF: u64 = 0x2;
-    const PSCI_FN_CPU_ON: u64 = 0x3;
-    const _PSCI_FN_MIGRATE: u64 = 0x5;
-    const PSCI_FN_SYSTEM_OFF: u64 = 0x8;
-    const _PSCI_FN_SYSTEM_RESET: u64 = 0x9;
-    const PSCI_FN_END: u64 = 0x1f;
-
-    let fn_ = ctx.gpr[0];
-    let fn_offset = if PSCI_FN_RANGE_32.contains(&fn_) {
-        Some(fn_ - PSCI_FN_RANGE_32.start())
-    } else if PSCI_FN_RANGE_64.contains(&fn_) {
-        Some(fn_ - PSCI_FN_RANGE_64.start())
+/// The ISS has the MCR/MRC layout, with the `reg` field of the `VMRS` in `CRn`. The read is
+/// answered from the ID register view of the vCPU, without a VM exit.
+fn handle_vmrs_id_access<H: AxVCpuHal>(vcpu: &mut Aarch64VCpu<H>) -> AxResult<AxVCpuExitReason> {
+    let iss = ESR_EL2.read(ESR_EL2::ISS);
+
+    let id_reg = match (iss >> 10) & 0xf {
+        0b0111 => IdRegisters::MVFR0_EL1,
+        0b0110 => IdRegisters::MVFR1_EL1,
+        0b0101 => IdRegisters::MVFR2_EL1,
+        _ => return fail_entry(vcpu, "Unhandled VMRS access"),
+    };
+    let value = vcpu.id_reg(id_reg);
+    let reg = exception_sysreg_gpr(iss) as usize;
+    skip_trapped_instruction(&mut vcpu.ctx);
+    vcpu.ctx.set_gpr(reg, value as u32 as usize);
+    Ok(AxVCpuExitReason::Nothing)
+}
+
+/// Handles a trapped 64-bit MCRR or MRRC access to a CP15 register from an AArch32 guest.
+///
+/// The registers are numbered as the AArch64 registers they are mapped to, see
+/// [`exception_cp15_64_addr`], and accesses to other registers are reported as
+/// [`AxVCpuExitReason::FailEntry`]. A MCRR of `Rt2:Rt` is reported as a system register write of
+/// the 64-bit value. As a system register read can only have one destination register, a MRRC is
+/// completed here by the vCPU instead, see [`Aarch64VCpu::cp15_64_read`].
+fn handle_cp15_64_access<H: AxVCpuHal>(vcpu: &mut Aarch64VCpu<H>) -> AxResult<AxVCpuExitReason> {
+    let iss = ESR_EL2.read(ESR_EL2::ISS);
+
+    let Some(addr) = exception_cp15_64_addr(iss as usize) else {
+        return fail_entry(vcpu, "Unhandled MCRR/MRRC access");
+    };
+    let addr = SysRegAddr::new(addr);
+    let reg = exception_sysreg_gpr(iss) as usize;
+    let reg2 = exception_sysreg_gpr2(iss) as usize;
+    if exception_sysreg_direction_write(iss) {
+        defer_skip_trapped_instruction(vcpu);
+        let value = ((vcpu.ctx.gpr(reg2) as u32 as u64) << 32) | vcpu.ctx.gpr(reg) as u32 as u64;
+        return Ok(AxVCpuExitReason::SysRegWrite { addr, value });
+    }
+
+    let Some(value) = vcpu.cp15_64_read(addr) else {
+        return fail_entry(vcpu, "Unhandled MRRC access");
+    };
+    defer_skip_trapped_instruction(vcpu);
+    vcpu.ctx.set_gpr(reg, value as u32 as usize);
+    vcpu.ctx.set_gpr(reg2, (value >> 32) as usize);
+    Ok(AxVCpuExitReason::Nothing)
+}
+
+const PSCI_FN_RANGE_32: core::ops::RangeInclusive<u64> = 0x8400_0000..=0x8400_001F;
+const PSCI_FN_RANGE_64: core::ops::RangeInclusive<u64> = 0xC400_0000..=0xC400_001F;
+
+const PSCI_FN_VERSION: u64 = 0x0;
+const PSCI_FN_CPU_SUSPEND: u64 = 0x1;
+const PSCI_FN_CPU_OFF: u64 = 0x2;
+const PSCI_FN_CPU_ON: u64 = 0x3;
+const PSCI_FN_AFFINITY_INFO: u64 = 0x4;
+const PSCI_FN_MIGRATE_INFO_TYPE: u64 = 0x6;
+const PSCI_FN_SYSTEM_OFF: u64 = 0x8;
+const PSCI_FN_SYSTEM_RESET: u64 = 0x9;
+const PSCI_FN_FEATURES: u64 = 0xA;
+const PSCI_FN_SYSTEM_RESET2: u64 = 0x12;
+
+const PSCI_RET_SUCCESS: i64 = 0;
+const PSCI_RET_NOT_SUPPORTED: i64 = -1;
+const PSCI_RET_INVALID_PARAMETERS: i64 = -2;
+
+/// PSCI 1.1, as returned by `PSCI_VERSION`.
+const PSCI_VERSION_1_1: i64 = 0x0001_0001;
+/// `AFFINITY_INFO` state of an affinity instance that is powered on.
+const PSCI_AFFINITY_INFO_ON: i64 = 0;
+/// `MIGRATE_INFO_TYPE`: no Trusted OS present or the Trusted OS does not require migration.
+const PSCI_MIGRATE_INFO_TYPE_NOT_PRESENT: i64 = 2;
+/// `SYSTEM_RESET2` reset types with bit 31 set are vendor-specific.
+const PSCI_RESET2_TYPE_VENDOR: u32 = 1 << 31;
+/// The only architectural `SYSTEM_RESET2` reset type.
+const PSCI_RESET2_SYSTEM_WARM_RESET: u32 = 0;
+
+/// Returns whether the psci function at `fn_offset` is implemented with the given calling
+/// convention.
+///
+/// Functions without parameters or results wider than 32 bits only have a 32-bit function ID.
+fn psci_fn_implemented(fn_offset: u64, smc64: bool) -> bool {
+    match fn_offset {
+        PSCI_FN_CPU_SUSPEND | PSCI_FN_CPU_ON | PSCI_FN_AFFINITY_INFO | PSCI_FN_SYSTEM_RESET2 => {
+            true
+        }
+        PSCI_FN_VERSION
+        | PSCI_FN_CPU_OFF
+        | PSCI_FN_MIGRATE_INFO_TYPE
+        | PSCI_FN_SYSTEM_OFF
+        | PSCI_FN_SYSTEM_RESET
+        | PSCI_FN_FEATURES => !smc64,
+        _ => false,
+    }
+}
+
+/// Splits a function ID into its offset in the psci function range and whether the 64-bit
+/// calling convention is used.
+///
+/// Returns `None` if the function ID is not a psci function.
+fn psci_fn_decode(fn_id: u64) -> Option<(u64, bool)> {
+    if PSCI_FN_RANGE_32.contains(&fn_id) {
+        Some((fn_id - PSCI_FN_RANGE_32.start(), false))
+    } else if PSCI_FN_RANGE_64.contains(&fn_id) {
+        Some((fn_id - PSCI_FN_RANGE_64.start(), true))
     } else {
         None
+    }
+}
+
+/// Handles HVC or SMC exceptions that serve as psci (Power State Coordination Interface) calls.
+///
+/// A hvc or smc call with the function in range 0x8400_0000..=0x8400_001F (when the 32-bit
+/// hvc/smc calling convention is used) or 0xC400_0000..=0xC400_001F (when the 64-bit hvc/smc
+/// calling convention is used) is a psci call. PSCI 1.1 is emulated here for the guest, and psci
+/// calls are never forwarded to the firmware:
+///
+/// - `CPU_ON`, `CPU_OFF` and `SYSTEM_OFF` are reported as [`AxVCpuExitReason::CpuUp`],
+///   [`AxVCpuExitReason::CpuDown`] and [`AxVCpuExitReason::SystemDown`].
+/// - `AFFINITY_INFO` is reported as [`AxVCpuExitReason::Hypercall`], with
+///   [`Aarch64ExitDetail::PsciAffinityInfo`] recorded on the vCPU, as the power states of the
+///   other vCPUs are tracked by the VMM.
+/// - `CPU_SUSPEND` is emulated as a standby, reported as [`AxVCpuExitReason::Halt`] with
+///   [`Aarch64ExitDetail::Halt`] recorded on the vCPU.
+/// - `SYSTEM_RESET` and `SYSTEM_RESET2` are reported as [`AxVCpuExitReason::SystemDown`], with
+///   [`Aarch64ExitDetail::SystemReset`] recorded on the vCPU.
+/// - `PSCI_VERSION`, `PSCI_FEATURES` and `MIGRATE_INFO_TYPE` are answered
+///   directly, and all other functions return `NOT_SUPPORTED`.
+///
+/// Returns `None` if the call is not a psci call.
+fn handle_psci_call<H: AxVCpuHal>(vcpu: &mut Aarch64VCpu<H>) -> Option<AxResult<AxVCpuExitReason>> {
+    let ctx = &mut vcpu.ctx;
+    // The function ID is passed in `w0` for both calling conventions.
+    let (fn_offset, smc64) = psci_fn_decode(ctx.gpr[0] as u32 as u64)?;
+    // Only `w1`-`w3` are meaningful with the 32-bit calling convention.
+    let [arg1, arg2, arg3] = [1, 2, 3].map(|i| {
+        if smc64 {
+            ctx.gpr[i]
+        } else {
+            ctx.gpr[i] as u32 as u64
+        }
+    });
+
+    let ret = match fn_offset {
+        _ if !psci_fn_implemented(fn_offset, smc64) => PSCI_RET_NOT_SUPPORTED,
+        PSCI_FN_VERSION => PSCI_VERSION_1_1,
+        PSCI_FN_CPU_SUSPEND => {
+            // Powerdown states are emulated as standby too, which PSCI allows. Either way the
+            // vCPU resumes right after the call once it is woken up.
+            ctx.gpr[0] = PSCI_RET_SUCCESS as u64;
+            vcpu.exit_detail = Aarch64ExitDetail::Halt {
+                timer_deadline: vcpu.timer_next_deadline(),
+            };
+            return Some(Ok(AxVCpuExitReason::Halt));
+        }
+        PSCI_FN_CPU_OFF => return Some(Ok(AxVCpuExitReason::CpuDown { _state: ctx.gpr[1] })),
+        PSCI_FN_CPU_ON => {
+            // The VMM may overwrite the result with `set_return_value`.
+            ctx.gpr[0] = PSCI_RET_SUCCESS as u64;
+            return Some(Ok(AxVCpuExitReason::CpuUp {
+                target_cpu: arg1,
+                entry_point: GuestPhysAddr::from(arg2 as usize),
+                arg: arg3,
+            }));
+        }
+        PSCI_FN_AFFINITY_INFO => {
+            if arg2 > 3 {
+                PSCI_RET_INVALID_PARAMETERS
+            } else {
+                let function_id = ctx.gpr[0] as u32 as u64;
+                // The VMM overwrites the result for vCPUs that are not on.
+                ctx.gpr[0] = PSCI_AFFINITY_INFO_ON as u64;
+                vcpu.exit_detail = Aarch64ExitDetail::PsciAffinityInfo {
+                    target_affinity: arg1,
+                    lowest_affinity_level: arg2 as u32,
+                };
+                return Some(Ok(AxVCpuExitReason::Hypercall {
+                    nr: function_id,
+                    args: [arg1, arg2, 0, 0, 0, 0],
+                }));
+            }
+        }
+        PSCI_FN_MIGRATE_INFO_TYPE => PSCI_MIGRATE_INFO_TYPE_NOT_PRESENT,
+        PSCI_FN_SYSTEM_OFF => return Some(Ok(AxVCpuExitReason::SystemDown)),
+        PSCI_FN_SYSTEM_RESET => {
+            vcpu.exit_detail = Aarch64ExitDetail::SystemReset {
+                reset_type: None,
+                cookie: 0,
+            };
+            return Some(Ok(AxVCpuExitReason::SystemDown));
+        }
+        PSCI_FN_SYSTEM_RESET2 => {
+            let reset_type = arg1 as u32;
+            if reset_type & PSCI_RESET2_TYPE_VENDOR == 0
+                && reset_type != PSCI_RESET2_SYSTEM_WARM_RESET
+            {
+                PSCI_RET_INVALID_PARAMETERS
+            } else {
+                vcpu.exit_detail = Aarch64ExitDetail::SystemReset {
+                    reset_type: Some(reset_type),
+                    cookie: arg2,
+                };
+                return Some(Ok(AxVCpuExitReason::SystemDown));
+            }
+        }
+        // `SMCCC_VERSION` is discovered through `PSCI_FEATURES`.
+        PSCI_FN_FEATURES if arg1 as u32 == SMCCC_VERSION => PSCI_RET_SUCCESS,
+        PSCI_FN_FEATURES => match psci_fn_decode(arg1) {
+            // For `CPU_SUSPEND`, 0 means the original power state format and no support for
+            // OS-initiated mode.
+            Some((fn_offset, smc64)) if psci_fn_implemented(fn_offset, smc64) => PSCI_RET_SUCCESS,
+            _ => PSCI_RET_NOT_SUPPORTED,
+        },
+        _ => PSCI_RET_NOT_SUPPORTED,
     };
 
-    match fn_offset {
-        Some(PSCI_FN_CPU_OFF) => Some(Ok(AxVCpuExitReason::CpuDown { _state: ctx.gpr[1] })),
-        Some(PSCI_FN_CPU_ON) => Some(Ok(AxVCpuExitReason::CpuUp {
-            target_cpu: ctx.gpr[1],
-            entry_point: GuestPhysAddr::from(ctx.gpr[2] as usize),
-            arg: ctx.gpr[3],
-        })),
-        Some(PSCI_FN_SYSTEM_OFF) => Some(Ok(AxVCpuExitReason::SystemDown)),
-        // We just forward these request to the ATF directly.
-        Some(PSCI_FN_VERSION..PSCI_FN_END) => None,
-        _ => None,
+    ctx.gpr[0] = if smc64 { ret as u64 } else { ret as u32 as u64 };
+    Some(Ok(AxVCpuExitReason::Nothing))
+}
+
+/// Handles HVC or SMC exceptions that serve as SMCCC Arm architecture calls.
+///
+/// A hvc or smc call with the function in range 0x8000_0000..=0x8000_FFFF is an Arm architecture
+/// call, which is emulated here without exiting to the VMM:
+///
+/// - `SMCCC_VERSION` reports SMCCC v1.1, and `SMCCC_ARCH_FEATURES` reports the calls below and
+///   the state of the host CPU regarding each `SMCCC_ARCH_WORKAROUND_*` call.
+/// - `SMCCC_ARCH_WORKAROUND_1` and `SMCCC_ARCH_WORKAROUND_3` are forwarded to the firmware if it
+///   mitigates the host CPU, and do nothing if the host CPU is not affected.
+/// - `SMCCC_ARCH_WORKAROUND_2` is never advertised: the firmware mitigation is permanently
+///   enabled for guests if the host CPU needs it, unless the guest can control it with
+///   `PSTATE.SSBS`.
+///
+/// All other calls, including `SMCCC_ARCH_SOC_ID`, return `NOT_SUPPORTED`.
+///
+/// Returns `None` if the call is not an Arm architecture call.
+fn handle_smccc_arch_call<H: AxVCpuHal>(
+    vcpu: &mut Aarch64VCpu<H>,
+) -> Option<AxResult<AxVCpuExitReason>> {
+    const SMCCC_ARCH_FN_RANGE: core::ops::RangeInclusive<u32> = 0x8000_0000..=0x8000_FFFF;
+
+    const SMCCC_RET_SUCCESS: i32 = 0;
+    const SMCCC_RET_NOT_SUPPORTED: i32 = -1;
+    const SMCCC_RET_NOT_REQUIRED: i32 = -2;
+    /// `SMCCC_ARCH_FEATURES` result for a workaround that the calling PE does not need.
+    const SMCCC_ARCH_WORKAROUND_RET_UNAFFECTED: i32 = 1;
+    /// SMCCC v1.1, as returned by `SMCCC_VERSION`.
+    const SMCCC_VERSION_1_1: i32 = 0x0001_0001;
+
+    let ctx = &mut vcpu.ctx;
+    let fn_id = ctx.gpr[0] as u32;
+    if !SMCCC_ARCH_FN_RANGE.contains(&fn_id) {
+        return None;
     }
+
+    let workaround_features = |state| match state {
+        WorkaroundState::Mitigated => SMCCC_RET_SUCCESS,
+        WorkaroundState::Unaffected => SMCCC_ARCH_WORKAROUND_RET_UNAFFECTED,
+        WorkaroundState::Vulnerable => SMCCC_RET_NOT_SUPPORTED,
+    };
+    let workaround_call = |fn_id, state| match state {
+        WorkaroundState::Mitigated => {
+            // The workaround takes no arguments, and it is safe to call the ATF with it.
+            unsafe { crate::smc::smc_call(fn_id as u64, 0, 0, 0) };
+            SMCCC_RET_SUCCESS
+        }
+        WorkaroundState::Unaffected => SMCCC_RET_SUCCESS,
+        WorkaroundState::Vulnerable => SMCCC_RET_NOT_SUPPORTED,
+    };
+
+    let host = host_workarounds();
+    let ret = match fn_id {
+        SMCCC_VERSION => SMCCC_VERSION_1_1,
+        SMCCC_ARCH_FEATURES => match ctx.gpr[1] as u32 {
+            SMCCC_VERSION | SMCCC_ARCH_FEATURES => SMCCC_RET_SUCCESS,
+            SMCCC_ARCH_WORKAROUND_1 => workaround_features(host.wa1),
+            SMCCC_ARCH_WORKAROUND_3 => workaround_features(host.wa3),
+            SMCCC_ARCH_WORKAROUND_2 => match host.wa2 {
+                // Leave the mitigation to the guest.
+                _ if host.ssbs => SMCCC_RET_NOT_SUPPORTED,
+                WorkaroundState::Mitigated | WorkaroundState::Unaffected => SMCCC_RET_NOT_REQUIRED,
+                WorkaroundState::Vulnerable => SMCCC_RET_NOT_SUPPORTED,
+            },
+            SMCCC_ARCH_SOC_ID => SMCCC_RET_NOT_SUPPORTED,
+            _ => SMCCC_RET_NOT_SUPPORTED,
+        },
+        SMCCC_ARCH_WORKAROUND_1 => workaround_call(SMCCC_ARCH_WORKAROUND_1, host.wa1),
+        SMCCC_ARCH_WORKAROUND_3 => workaround_call(SMCCC_ARCH_WORKAROUND_3, host.wa3),
+        _ => SMCCC_RET_NOT_SUPPORTED,
+    };
+
+    ctx.gpr[0] = ret as u32 as u64;
+    Some(Ok(AxVCpuExitReason::Nothing))
 }
 
 /// Handles SMC (Secure Monitor Call) exceptions.
 ///
-/// This function will judge if the SMC call is a PSCI call, if so, it will handle it as a PSCI call.
-/// Otherwise, it will forward the SMC call to the ATF directly.
-fn handle_smc64_exception(ctx: &mut TrapFrame) -> AxResult<AxVCpuExitReason> {
+/// This function will judge if the SMC call is a PSCI call or an Arm architecture call, if so, it
+/// will be emulated by [`handle_psci_call`] or [`handle_smccc_arch_call`]. Otherwise, the call is forwarded to the ATF, denied or reported to the VMM according to the
+/// [`SmcPolicy`](crate::SmcPolicy) of the vCPU.
+///
+/// `SMC32` calls from AArch32 guests are handled here too, as they use the same registers.
+fn handle_smc64_exception<H: AxVCpuHal>(vcpu: &mut Aarch64VCpu<H>) -> AxResult<AxVCpuExitReason> {
+    /// `NOT_SUPPORTED` of the SMC Calling Convention, for unknown function IDs.
+    const SMCCC_RET_NOT_SUPPORTED: u64 = -1i64 as u64;
+
     // Is this a psci call?
-    if let Some(result) = handle_psci_call(ctx) {
-        result
+    if let Some(result) = handle_psci_call(vcpu) {
+        return result;
+    }
+    if let Some(result) = handle_smccc_arch_call(vcpu) {
+        return result;
+    }
+
+    let ctx = &mut vcpu.ctx;
+    let function_id = ctx.gpr[0] as u32;
+    // SMCCC v1.2 passes arguments and results in `x0`-`x17`, and in `r0`-`r7` for AArch32
+    // callers, whose other registers must not be clobbered.
+    let aarch32 = ctx.is_aarch32();
+    let nr_regs = if aarch32 { 8 } else { 18 };
+    let fallback = if vcpu.smc_policy.forward.contains(&function_id) {
+        SmcFallback::Forward
     } else {
-        // We just forward the SMC call to the ATF directly.
-        // The args are from lower EL, so it is safe to call the ATF.
-        (ctx.gpr[0], ctx.gpr[1], ctx.gpr[2], ctx.gpr[3]) =
-            unsafe { crate::smc::smc_call(ctx.gpr[0], ctx.gpr[1], ctx.gpr[2], ctx.gpr[3]) };
-        Ok(AxVCpuExitReason::Nothing)
+        vcpu.smc_policy.fallback
+    };
+
+    match fallback {
+        SmcFallback::Deny => {
+            debug!(
+                "Denied SMC call {:#x} @pc {:#x}",
+                function_id,
+                ctx.exception_pc()
+            );
+            ctx.gpr[0] = SMCCC_RET_NOT_SUPPORTED;
+            Ok(AxVCpuExitReason::Nothing)
+        }
+        SmcFallback::Forward => {
+            // The args are from lower EL, so it is safe to call the ATF.
+            let mut regs = [0; 18];
+            regs[..nr_regs].copy_from_slice(&ctx.gpr[..nr_regs]);
+            if aarch32 {
+                regs.iter_mut().for_each(|reg| *reg = *reg as u32 as u64);
+            }
+            unsafe { crate::smc::smc_call_v1_2(&mut regs) };
+            if aarch32 {
+                regs.iter_mut().for_each(|reg| *reg = *reg as u32 as u64);
+            }
+            ctx.gpr[..nr_regs].copy_from_slice(&regs[..nr_regs]);
+            Ok(AxVCpuExitReason::Nothing)
+        }
+        SmcFallback::Exit => {
+            let mut args = [0; 17];
+            args[..nr_regs - 1].copy_from_slice(&ctx.gpr[1..nr_regs]);
+            vcpu.exit_detail = Aarch64ExitDetail::SmcCall { function_id, args };
+            Ok(AxVCpuExitReason::Hypercall {
+                nr: ctx.gpr[0],
+                args: [
+                    ctx.gpr[1], ctx.gpr[2], ctx.gpr[3], ctx.gpr[4], ctx.gpr[5], ctx.gpr[6],
+                ],
+            })
+        }
     }
 }
 
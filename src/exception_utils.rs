@@ -2,6 +2,8 @@ use aarch64_cpu::registers::*;
 use axaddrspace::GuestPhysAddr;
 use axerrno::{AxResult, ax_err};
 
+use crate::exit::SErrorSeverity;
+
 /// Retrieves the Exception Syndrome Register (ESR) value from EL2.
 ///
 /// # Returns
@@ -20,6 +22,14 @@ pub fn exception_class() -> Option<ESR_EL2::EC::Value> {
     ESR_EL2.read_as_enum(ESR_EL2::EC)
 }
 
+/// Exception class of AArch32 `VMRS` reads of `MVFR0`-`MVFR2` trapped by `HCR_EL2.TID3`, not
+/// listed in [`ESR_EL2::EC::Value`].
+pub const ESR_EC_VMRS_ID: usize = 0b00_1000;
+/// Exception class of HVC instructions executed in AArch32 state, not listed in [`ESR_EL2::EC::Value`].
+pub const ESR_EC_HVC32: usize = 0b01_0010;
+/// Exception class of SMC instructions executed in AArch32 state, not listed in [`ESR_EL2::EC::Value`].
+pub const ESR_EC_SMC32: usize = 0b01_0011;
+
 /// Reads the Exception Class (EC) field from the ESR_EL2 register and returns it as a raw value.
 ///
 /// # Returns
@@ -36,7 +46,7 @@ pub fn exception_class_value() -> usize {
 /// # Returns
 /// The value of the HPFAR_EL2 register as a `usize`.
 #[inline(always)]
-fn exception_hpfar() -> usize {
+pub fn exception_hpfar() -> usize {
     let hpfar: u64;
     unsafe {
         core::arch::asm!("mrs {}, HPFAR_EL2", out(reg) hpfar);
@@ -110,6 +120,34 @@ fn translate_far_to_hpfar(far: usize) -> AxResult<usize> {
     }
 }
 
+/// Translates the guest virtual address `va` to an IPA, with the stage 1 translation regime of
+/// the guest EL1&0.
+///
+/// # Errors
+/// Returns a `BadState` error if the translation is aborted, e.g. the guest page tables changed.
+pub fn translate_guest_va(va: usize) -> AxResult<GuestPhysAddr> {
+    let hpfar = translate_far_to_hpfar(va)?;
+    Ok(GuestPhysAddr::from((va & 0xfff) | (hpfar << 8)))
+}
+
+/// Translates the guest virtual address `va` to a host physical address, with both the stage 1
+/// and the stage 2 translation of the guest.
+///
+/// # Errors
+/// Returns a `BadState` error if the translation is aborted at either stage.
+pub fn translate_guest_va_to_pa(va: usize) -> AxResult<usize> {
+    let par = PAR_EL1.get();
+    arm_at!("s12e1r", va);
+    let tmp = PAR_EL1.get();
+    PAR_EL1.set(par);
+    if (tmp & PAR_EL1::F::TranslationAborted.value) != 0 {
+        ax_err!(BadState, "PAR_EL1::F::TranslationAborted value")
+    } else {
+        let mask = ((1 << (52 - 12)) - 1) << 12;
+        Ok((tmp & mask) as usize | (va & 0xfff))
+    }
+}
+
 /// Retrieves the fault address that caused an exception.
 ///
 /// This function returns the Guest Physical Address (GPA) that caused the
@@ -192,6 +230,166 @@ pub const fn exception_sysreg_addr(iss: usize) -> usize {
     iss & ESR_ISS_SYSREG_ADDR
 }
 
+/// Checks if a trapped WFx instruction is WFE (or WFET).
+///
+/// # Returns
+/// - `true` if the instruction is WFE or WFET.
+/// - `false` if the instruction is WFI or WFIT.
+#[inline(always)]
+pub fn exception_wfx_is_wfe() -> bool {
+    (exception_iss() & 0b1) != 0
+}
+
+/// Decodes the severity of an SError from the ESR_EL2 register.
+///
+/// The severity is given by `ESR_EL2.ISS.AET` when `ESR_EL2.ISS.IDS` is clear and
+/// `ESR_EL2.ISS.DFSC` reports an asynchronous SError, and is unknown otherwise.
+pub fn exception_serror_severity() -> SErrorSeverity {
+    const ESR_ISS_SERROR_IDS: usize = 1 << 24;
+    const ESR_ISS_SERROR_AET_SHIFT: usize = 10;
+    const ESR_ISS_SERROR_DFSC_MASK: usize = 0x3f;
+    const ESR_ISS_SERROR_DFSC_ASYNC: usize = 0b01_0001;
+
+    let iss = exception_iss();
+    if iss & ESR_ISS_SERROR_IDS != 0 || iss & ESR_ISS_SERROR_DFSC_MASK != ESR_ISS_SERROR_DFSC_ASYNC
+    {
+        return SErrorSeverity::Uncategorized;
+    }
+    match (iss >> ESR_ISS_SERROR_AET_SHIFT) & 0b111 {
+        0b000 => SErrorSeverity::Uncontainable,
+        0b001 => SErrorSeverity::Unrecoverable,
+        0b010 => SErrorSeverity::Restartable,
+        0b011 => SErrorSeverity::Recoverable,
+        0b110 => SErrorSeverity::Corrected,
+        _ => SErrorSeverity::Uncategorized,
+    }
+}
+
+/// The MCR/MRC (and MRRC) access to CP15 registers, when taken from AArch32, has the same
+/// layout of `Opc2`, `Opc1`, `CRn` and `CRm` as the MSR/MRS ISS, without `Op0`, i.e.
+/// `<opc2><opc1><CRn>00000<CRm>0` in bits 19 to 1.
+///
+/// So CP15 registers are numbered as AArch64 system registers with `Op0` equal to 0, which is
+/// never used by trapped MSR/MRS accesses.
+#[inline(always)]
+pub const fn exception_cp15_addr(iss: usize) -> usize {
+    const ESR_ISS_CP15_ADDR: usize = (0x3ff << 10) | (0xf << 1);
+    iss & ESR_ISS_CP15_ADDR
+}
+
+/// Returns the AArch64 system register, numbered as [`exception_sysreg_addr`], that the 64-bit
+/// CP15 register accessed by a trapped MCRR/MRRC is architecturally mapped to, e.g. `TTBR0_EL1`
+/// for `TTBR0`.
+///
+/// The MCRR/MRRC ISS only has `Opc1` in bits 19 to 16 and `CRm` in bits 4 to 1. Returns `None`
+/// for the registers that are not emulated.
+#[inline(always)]
+pub const fn exception_cp15_64_addr(iss: usize) -> Option<usize> {
+    const SYSREG_TTBR0_EL1: usize = 0x30_0800;
+    const SYSREG_TTBR1_EL1: usize = 0x32_0800;
+    const SYSREG_CNTPCT_EL0: usize = 0x32_f800;
+    const SYSREG_CNTP_CVAL_EL0: usize = 0x34_f804;
+    match ((iss >> 16) & 0xf, (iss >> 1) & 0xf) {
+        (0, 2) => Some(SYSREG_TTBR0_EL1),
+        (1, 2) => Some(SYSREG_TTBR1_EL1),
+        (0, 14) => Some(SYSREG_CNTPCT_EL0),
+        (2, 14) => Some(SYSREG_CNTP_CVAL_EL0),
+        _ => None,
+    }
+}
+
+/// Returns `Rt2`, the register holding the high 32 bits of a trapped MCRR/MRRC access.
+#[inline(always)]
+pub fn exception_sysreg_gpr2(iss: u64) -> u64 {
+    (iss >> 10) & 0x1f
+}
+
+/// Checks if a trapped instruction taken from AArch32 state passed its condition code check.
+///
+/// A conditional AArch32 instruction may be trapped even if it fails its condition code check,
+/// in which case it must be skipped instead of being emulated. The condition is taken from
+/// `ESR_EL2.ISS.COND` if `ESR_EL2.ISS.CV` is set, and from the IT state in `spsr` otherwise.
+///
+/// # Arguments
+/// * `spsr` - The `SPSR_EL2` value of the trapped guest, holding the condition flags and the IT state.
+///
+/// # Returns
+/// - `true` if the instruction is unconditional or passed its condition code check.
+/// - `false` otherwise.
+pub fn exception_aarch32_condition_passed(spsr: u64) -> bool {
+    // Bit `n` of `CC_MAP[cond]` tells whether `cond` passes with `NZCV` equal to `n`.
+    const CC_MAP: [u16; 16] = [
+        0xF0F0, // EQ == Z set
+        0x0F0F, // NE
+        0xCCCC, // CS == C set
+        0x3333, // CC
+        0xFF00, // MI == N set
+        0x00FF, // PL
+        0xAAAA, // VS == V set
+        0x5555, // VC
+        0x0C0C, // HI == C set && Z clear
+        0xF3F3, // LS == C clear || Z set
+        0xAA55, // GE == (N==V)
+        0x55AA, // LT == (N!=V)
+        0x0A05, // GT == (!Z && (N==V))
+        0xF5FA, // LE == (Z || (N!=V))
+        0xFFFF, // AL always
+        0,      // NV
+    ];
+    const ESR_ISS_CV: usize = 1 << 24;
+
+    let esr = exception_esr();
+
+    // Exception classes with EC[5:4] != 0 are taken for unconditional instructions only.
+    if (esr >> 30) & 0b11 != 0 {
+        return true;
+    }
+
+    let cond = if esr & ESR_ISS_CV != 0 {
+        (esr >> 20) & 0xf
+    } else {
+        // This can happen in T32 state, examine the IT state.
+        let it = ((spsr >> 8) & 0xfc) | ((spsr >> 25) & 0x3);
+        if it == 0 {
+            return true;
+        }
+        (it >> 4) as usize
+    };
+
+    let nzcv = (spsr >> 28) & 0xf;
+    (CC_MAP[cond] >> nzcv) & 1 != 0
+}
+
+/// Advances the IT state in `spsr` of a context in AArch32 T32 state, as if the current
+/// instruction in the IT block has been executed.
+///
+/// `spsr` is returned unmodified if the context is not in T32 state or not in an IT block.
+pub const fn aarch32_it_advance(spsr: u64) -> u64 {
+    use crate::context_frame::{SPSR_AARCH32_IT_MASK, SPSR_AARCH32_T};
+
+    if spsr & SPSR_AARCH32_T == 0 || spsr & SPSR_AARCH32_IT_MASK == 0 {
+        return spsr;
+    }
+
+    let mut cond = (spsr & 0xe000) >> 13;
+    let mut itbits = (spsr & 0x1c00) >> (10 - 2);
+    itbits |= (spsr & (0x3 << 25)) >> 25;
+
+    // Perform ITAdvance.
+    if itbits & 0x7 == 0 {
+        itbits = 0;
+        cond = 0;
+    } else {
+        itbits = (itbits << 1) & 0x1f;
+    }
+
+    let mut spsr = spsr & !SPSR_AARCH32_IT_MASK;
+    spsr |= cond << 13;
+    spsr |= (itbits & 0x1c) << (10 - 2);
+    spsr |= (itbits & 0x3) << 25;
+    spsr
+}
+
 /// Checks if the data abort exception was caused by a permission fault.
 ///
 /// # Returns
@@ -211,10 +409,13 @@ pub fn exception_data_abort_access_width() -> usize {
     1 << ((exception_iss() >> 22) & 0b11)
 }
 
-/// Determines the DA can be handled
+/// Checks if the data abort exception has a valid instruction syndrome (`ISS.ISV`), i.e., the
+/// access width, register and direction in the ISS are valid.
+///
+/// Without it, the access has to be emulated by decoding the faulting instruction.
 #[inline(always)]
 pub fn exception_data_abort_handleable() -> bool {
-    (!(exception_iss() & (1 << 10)) | (exception_iss() & (1 << 24))) != 0
+    (exception_iss() & (1 << 24)) != 0
 }
 
 #[inline(always)]
@@ -222,6 +423,26 @@ pub fn exception_data_abort_is_translate_fault() -> bool {
     (exception_iss() & 0b111111 & (0xf << 2)) == 4
 }
 
+/// Checks if the abort exception was caused by an access flag fault.
+#[inline(always)]
+pub fn exception_abort_is_access_flag_fault() -> bool {
+    (exception_iss() & 0b111111 & (0xf << 2)) == 8
+}
+
+/// Checks if the abort exception happened on a stage 1 translation table walk (`ISS.S1PTW`).
+#[inline(always)]
+pub fn exception_abort_is_s1ptw() -> bool {
+    (exception_esr() & ESR_ELx_S1PTW) != 0
+}
+
+/// Returns the translation table level of the abort exception, from the fault status code.
+///
+/// Only meaningful for translation, access flag and permission faults.
+#[inline(always)]
+pub fn exception_abort_fault_level() -> u8 {
+    (exception_iss() & 0b11) as u8
+}
+
 /// Checks if the data abort exception was caused by a write access.
 ///
 /// # Returns
@@ -256,12 +477,21 @@ pub fn exception_data_abort_access_reg_width() -> usize {
 /// # Returns
 /// - `true` if the data is sign-extended.
 /// - `false` otherwise.
-#[allow(unused)]
 #[inline(always)]
 pub fn exception_data_abort_access_is_sign_ext() -> bool {
     ((exception_iss() >> 21) & 1) != 0
 }
 
+/// Checks if the data abort exception was caused by a load-acquire or store-release (`ISS.AR`).
+///
+/// # Returns
+/// - `true` if the access has acquire/release semantics.
+/// - `false` otherwise.
+#[inline(always)]
+pub fn exception_data_abort_access_is_acquire_release() -> bool {
+    ((exception_iss() >> 14) & 1) != 0
+}
+
 /// Macro to save the host function context to the stack.
 ///
 /// This macro saves the values of the callee-saved registers (`x19` to `x30`) to the stack.
@@ -0,0 +1,212 @@
+// Copyright 2025 The Axvisor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/// AArch64 specific details of the last VM exit.
+///
+/// [`AxVCpuExitReason`](axvcpu::AxVCpuExitReason) is shared by all architectures and can not
+/// describe every AArch64 event. Exits that need more information are reported with the closest
+/// generic exit reason, and the details are recorded on the vCPU, which can be retrieved with
+/// [`Aarch64VCpu::exit_detail`](crate::Aarch64VCpu::exit_detail) after `run` returns.
+///
+/// The details are reset on every VM exit.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Aarch64ExitDetail {
+    /// No more details than the exit reason itself.
+    #[default]
+    None,
+    /// The guest requested a system reset through PSCI `SYSTEM_RESET` or `SYSTEM_RESET2`.
+    ///
+    /// Reported as [`AxVCpuExitReason::SystemDown`](axvcpu::AxVCpuExitReason::SystemDown).
+    SystemReset {
+        /// The `reset_type` argument of `SYSTEM_RESET2`, or `None` for a `SYSTEM_RESET`
+        /// (cold reset).
+        ///
+        /// Bit 31 set means a vendor-specific reset, otherwise it is an architectural reset,
+        /// for which only `0` (`SYSTEM_WARM_RESET`) is defined.
+        reset_type: Option<u32>,
+        /// The `cookie` argument of `SYSTEM_RESET2`, only meaningful for vendor-specific resets.
+        cookie: u64,
+    },
+    /// The guest waits for an interrupt, after a trapped WFI or a PSCI `CPU_SUSPEND`.
+    ///
+    /// Reported as [`AxVCpuExitReason::Halt`](axvcpu::AxVCpuExitReason::Halt). The vCPU should be
+    /// blocked until an interrupt is pending for it, or until `timer_deadline`, when the PPI of
+    /// the expired timer has to be injected. This includes the emulated EL1 physical timer, which
+    /// nothing else would wake the vCPU for.
+    Halt {
+        /// The earliest deadline of the timers of the guest, in ticks of the host counter, see
+        /// [`Aarch64VCpu::timer_next_deadline`](crate::Aarch64VCpu::timer_next_deadline).
+        timer_deadline: Option<u64>,
+    },
+    /// The guest asked for the power state of another vCPU with PSCI `AFFINITY_INFO`, which only
+    /// the VMM knows.
+    ///
+    /// Reported as [`AxVCpuExitReason::Hypercall`](axvcpu::AxVCpuExitReason::Hypercall), with the
+    /// function ID and arguments of the call. The result is preset to `ON` (0) in `x0`, and should
+    /// be overwritten with `set_return_value` by the VMM, as `OFF` (1) or `ON_PENDING` (2) for
+    /// vCPUs that are not running.
+    PsciAffinityInfo {
+        /// The `target_affinity` argument, the MPIDR affinity fields of the vCPU queried.
+        target_affinity: u64,
+        /// The `lowest_affinity_level` argument, 0 for a single vCPU.
+        lowest_affinity_level: u32,
+    },
+    /// The guest made an SMC call to be emulated by the VMM, as [`SmcFallback::Exit`] requests.
+    ///
+    /// Reported as [`AxVCpuExitReason::Hypercall`](axvcpu::AxVCpuExitReason::Hypercall). The guest
+    /// PC has been advanced past the `smc` instruction, and the results of the call should be
+    /// written to `x0`-`x17` with `set_gpr`, or to `r0`-`r7` for AArch32 guests.
+    ///
+    /// [`SmcFallback::Exit`]: crate::SmcFallback::Exit
+    SmcCall {
+        /// The function ID in `w0`.
+        function_id: u32,
+        /// The arguments in `x1`-`x17`, as defined by SMCCC v1.2. For AArch32 guests, only
+        /// `r1`-`r7` are arguments, and the rest is zero.
+        args: [u64; 17],
+    },
+    /// The guest triggered an exception that can not be handled, e.g. an exception of an
+    /// unknown class or an unsupported data abort.
+    ///
+    /// Reported as [`AxVCpuExitReason::FailEntry`](axvcpu::AxVCpuExitReason::FailEntry), with
+    /// `ESR_EL2` as the `hardware_entry_failure_reason`. The guest state is left as it was when
+    /// the exception was taken, so the VM should be stopped or reset rather than resumed.
+    FailEntry {
+        /// The `ESR_EL2` value of the exception.
+        esr: u64,
+        /// The exception class, `ESR_EL2.EC`.
+        ec: u8,
+        /// The `FAR_EL2` value of the exception, only valid for aborts.
+        far: u64,
+        /// The `HPFAR_EL2` value of the exception, only valid for stage 2 aborts.
+        hpfar: u64,
+        /// The guest PC of the exception.
+        elr: u64,
+    },
+    /// The guest accessed memory that its stage 2 mapping does not allow, e.g. wrote to a page
+    /// write-protected for dirty logging or copy-on-write, or fetched an instruction from an
+    /// unmapped IPA.
+    ///
+    /// Reported as [`AxVCpuExitReason::NestedPageFault`](axvcpu::AxVCpuExitReason::NestedPageFault)
+    /// with the faulting IPA and access. The guest PC is left at the faulting instruction, so it
+    /// is retried when the guest is resumed after the VMM fixed up the stage 2 mapping.
+    Stage2Fault {
+        /// The faulting intermediate physical address.
+        ipa: u64,
+        /// The attempted access.
+        access: FaultAccess,
+        /// The kind of the fault.
+        kind: Stage2FaultKind,
+        /// Whether the fault happened on a stage 1 translation table walk of the guest, in
+        /// which case `ipa` is the address of a guest translation table, and `access` is
+        /// [`FaultAccess::Write`], as the walk may update the descriptors.
+        s1ptw: bool,
+        /// The level of the stage 2 translation table where the fault happened, 0 to 3.
+        level: u8,
+    },
+    /// The guest accessed an MMIO region.
+    ///
+    /// Reported as [`AxVCpuExitReason::MmioRead`](axvcpu::AxVCpuExitReason::MmioRead) or
+    /// [`AxVCpuExitReason::MmioWrite`](axvcpu::AxVCpuExitReason::MmioWrite). A read should be
+    /// completed with [`Aarch64VCpu::complete_mmio_read`](crate::Aarch64VCpu::complete_mmio_read).
+    ///
+    /// The data of the exits is in the byte order of the bus, i.e., byte-swapped if the guest
+    /// accesses data in big-endian, see [`Aarch64VCpu::data_endianness`], so that devices are
+    /// emulated the same way for all guests.
+    ///
+    /// [`Aarch64VCpu::data_endianness`]: crate::Aarch64VCpu::data_endianness
+    Mmio {
+        /// Whether the access is a load-acquire or a store-release, which must not be reordered
+        /// with the accesses around it.
+        acquire_release: bool,
+    },
+    /// The guest generated an SGI through `ICC_SGI0R_EL1` or `ICC_ASGI1R_EL1`.
+    ///
+    /// Reported as [`AxVCpuExitReason::SendIPI`](axvcpu::AxVCpuExitReason::SendIPI). SGIs
+    /// generated through `ICC_SGI1R_EL1`, the only register Linux uses, are Group 1 SGIs, and
+    /// have no details.
+    Group0Sgi,
+    /// The guest caused an SError (asynchronous abort), which has been taken to EL2.
+    ///
+    /// Reported as [`AxVCpuExitReason::Nothing`](axvcpu::AxVCpuExitReason::Nothing). The guest
+    /// can be resumed as is, the VMM decides whether to reflect the error to the guest with
+    /// [`Aarch64VCpu::inject_serror`](crate::Aarch64VCpu::inject_serror), or to stop the VM.
+    ///
+    /// Fatal errors, see [`SErrorSeverity::is_fatal`], are reported as
+    /// [`AxVCpuExitReason::FailEntry`](axvcpu::AxVCpuExitReason::FailEntry) instead, with `esr`
+    /// as the `hardware_entry_failure_reason`, as the VM must not be resumed.
+    SError {
+        /// The `ESR_EL2` value of the SError.
+        esr: u64,
+        /// The severity of the error, decoded from `esr`.
+        severity: SErrorSeverity,
+    },
+}
+
+/// The severity of an SError, as defined by the RAS extension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SErrorSeverity {
+    /// The syndrome is IMPLEMENTATION DEFINED or uncategorized, so the severity is unknown.
+    Uncategorized,
+    /// Uncontainable (UC), the error may have corrupted any state of the system.
+    Uncontainable,
+    /// Unrecoverable (UEU), the error is contained, but execution can not continue.
+    Unrecoverable,
+    /// Restartable (UEO), the error did not affect the interrupted execution, which can continue.
+    Restartable,
+    /// Recoverable (UER), the error is contained, and software can recover from it.
+    Recoverable,
+    /// Corrected (CE), the error has been corrected by the hardware.
+    Corrected,
+}
+
+impl SErrorSeverity {
+    /// Returns whether execution can not continue after the error, i.e., whether it is
+    /// Uncontainable or Unrecoverable.
+    pub const fn is_fatal(self) -> bool {
+        matches!(self, Self::Uncontainable | Self::Unrecoverable)
+    }
+}
+
+/// The byte order of the data accesses of a guest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Endianness {
+    /// Little-endian.
+    Little,
+    /// Big-endian.
+    Big,
+}
+
+/// The access that caused a stage 2 fault.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultAccess {
+    /// A data read.
+    Read,
+    /// A data write.
+    Write,
+    /// An instruction fetch.
+    Exec,
+}
+
+/// The kind of a stage 2 fault, from the fault status code of the abort.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage2FaultKind {
+    /// The IPA is not mapped at stage 2.
+    Translation,
+    /// The access flag of the stage 2 descriptor is not set.
+    AccessFlag,
+    /// The stage 2 descriptor does not permit the access.
+    Permission,
+}
@@ -0,0 +1,198 @@
+// Copyright 2025 The Axvisor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#[cfg(target_arch = "aarch64")]
+use core::arch::asm;
+
+use axaddrspace::device::SysRegAddr;
+
+/// The number of feature ID registers, in `op0 = 3, op1 = 0, CRn = 0, CRm = 1..=7`.
+const NR_ID_REGS: usize = 7 * 8;
+
+/// Reads the host ID registers `S3_0_C0_C<crm>_<op2>` of one `CRm` into `regs`.
+#[cfg(target_arch = "aarch64")]
+macro_rules! read_id_regs {
+    ($regs:ident, $crm:literal, [$($op2:literal),*]) => {
+        $(
+            asm!(
+                concat!("mrs {0}, S3_0_C0_C", stringify!($crm), "_", stringify!($op2)),
+                out(reg) $regs[($crm - 1) * 8 + $op2],
+            );
+        )*
+    };
+}
+
+/// The feature ID registers a guest sees, trapped by `HCR_EL2.TID3`.
+///
+/// These are the registers in `op0 = 3, op1 = 0, CRn = 0, CRm = 1..=7`, i.e. `ID_AA64PFR0_EL1`,
+/// `ID_AA64ISAR0_EL1`, `ID_AA64MMFR0_EL1` and friends, as well as the AArch32 ones such as
+/// `ID_PFR0_EL1`, which AArch32 guests read with MRC from CP15. The view starts as a copy of the
+/// host registers, see [`Self::from_host`], and is then masked by the VMM, e.g. to the common
+/// features of all the cores a VM may run or migrate on.
+///
+/// The vCPU traps ID register reads and answers them from the view set in
+/// [`Aarch64VCpuSetupConfig::id_registers`], or the host one, without a VM exit.
+///
+/// [`Aarch64VCpuSetupConfig::id_registers`]: crate::Aarch64VCpuSetupConfig::id_registers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IdRegisters {
+    regs: [u64; NR_ID_REGS],
+}
+
+impl IdRegisters {
+    /// `ID_PFR0_EL1`, AArch32 Processor Feature Register 0.
+    pub const ID_PFR0_EL1: SysRegAddr = Self::addr(1, 0);
+    /// `ID_PFR1_EL1`, AArch32 Processor Feature Register 1.
+    pub const ID_PFR1_EL1: SysRegAddr = Self::addr(1, 1);
+    /// `ID_DFR0_EL1`, AArch32 Debug Feature Register 0.
+    pub const ID_DFR0_EL1: SysRegAddr = Self::addr(1, 2);
+    /// `MVFR0_EL1`, AArch32 Media and VFP Feature Register 0.
+    pub const MVFR0_EL1: SysRegAddr = Self::addr(3, 0);
+    /// `MVFR1_EL1`, AArch32 Media and VFP Feature Register 1.
+    pub const MVFR1_EL1: SysRegAddr = Self::addr(3, 1);
+    /// `MVFR2_EL1`, AArch32 Media and VFP Feature Register 2.
+    pub const MVFR2_EL1: SysRegAddr = Self::addr(3, 2);
+    /// `ID_ISAR0_EL1`, AArch32 Instruction Set Attribute Register 0.
+    pub const ID_ISAR0_EL1: SysRegAddr = Self::addr(2, 0);
+    /// `ID_AA64PFR0_EL1`, AArch64 Processor Feature Register 0.
+    pub const ID_AA64PFR0_EL1: SysRegAddr = Self::addr(4, 0);
+    /// `ID_AA64PFR1_EL1`, AArch64 Processor Feature Register 1.
+    pub const ID_AA64PFR1_EL1: SysRegAddr = Self::addr(4, 1);
+    /// `ID_AA64ZFR0_EL1`, SVE Feature ID Register 0.
+    pub const ID_AA64ZFR0_EL1: SysRegAddr = Self::addr(4, 4);
+    /// `ID_AA64SMFR0_EL1`, SME Feature ID Register 0.
+    pub const ID_AA64SMFR0_EL1: SysRegAddr = Self::addr(4, 5);
+    /// `ID_AA64DFR0_EL1`, AArch64 Debug Feature Register 0.
+    pub const ID_AA64DFR0_EL1: SysRegAddr = Self::addr(5, 0);
+    /// `ID_AA64DFR1_EL1`, AArch64 Debug Feature Register 1.
+    pub const ID_AA64DFR1_EL1: SysRegAddr = Self::addr(5, 1);
+    /// `ID_AA64AFR0_EL1`, AArch64 Auxiliary Feature Register 0.
+    pub const ID_AA64AFR0_EL1: SysRegAddr = Self::addr(5, 4);
+    /// `ID_AA64AFR1_EL1`, AArch64 Auxiliary Feature Register 1.
+    pub const ID_AA64AFR1_EL1: SysRegAddr = Self::addr(5, 5);
+    /// `ID_AA64ISAR0_EL1`, AArch64 Instruction Set Attribute Register 0.
+    pub const ID_AA64ISAR0_EL1: SysRegAddr = Self::addr(6, 0);
+    /// `ID_AA64ISAR1_EL1`, AArch64 Instruction Set Attribute Register 1.
+    pub const ID_AA64ISAR1_EL1: SysRegAddr = Self::addr(6, 1);
+    /// `ID_AA64ISAR2_EL1`, AArch64 Instruction Set Attribute Register 2.
+    pub const ID_AA64ISAR2_EL1: SysRegAddr = Self::addr(6, 2);
+    /// `ID_AA64MMFR0_EL1`, AArch64 Memory Model Feature Register 0.
+    pub const ID_AA64MMFR0_EL1: SysRegAddr = Self::addr(7, 0);
+    /// `ID_AA64MMFR1_EL1`, AArch64 Memory Model Feature Register 1.
+    pub const ID_AA64MMFR1_EL1: SysRegAddr = Self::addr(7, 1);
+    /// `ID_AA64MMFR2_EL1`, AArch64 Memory Model Feature Register 2.
+    pub const ID_AA64MMFR2_EL1: SysRegAddr = Self::addr(7, 2);
+
+    /// The `SysRegAddr` of the ID register `S3_0_C0_C<crm>_<op2>`.
+    const fn addr(crm: usize, op2: usize) -> SysRegAddr {
+        SysRegAddr::new((3 << 20) | (op2 << 17) | (crm << 1))
+    }
+
+    /// Returns the index of the ID register `addr` in `regs`.
+    ///
+    /// Both the AArch64 encoding and the AArch32 CP15 one, with `op0` equal to 0, are accepted.
+    const fn index(addr: SysRegAddr) -> Option<usize> {
+        let addr = addr.addr();
+        let op0 = (addr >> 20) & 0b11;
+        let op2 = (addr >> 17) & 0b111;
+        let crm = (addr >> 1) & 0xf;
+        // op1 and CRn must be 0, as well as the unused bits.
+        if addr & !((0b11 << 20) | (0b111 << 17) | (0xf << 1)) != 0 {
+            return None;
+        }
+        if (op0 != 3 && op0 != 0) || crm < 1 || crm > 7 {
+            return None;
+        }
+        Some((crm - 1) * 8 + op2)
+    }
+
+    /// Creates a view with all the ID registers reading as zero.
+    pub const fn new() -> Self {
+        Self {
+            regs: [0; NR_ID_REGS],
+        }
+    }
+
+    /// Creates a view of the ID registers of the current CPU.
+    #[cfg(target_arch = "aarch64")]
+    pub fn from_host() -> Self {
+        let mut regs = [0; NR_ID_REGS];
+        // Unallocated ID registers in this space read as zero.
+        unsafe {
+            read_id_regs!(regs, 1, [0, 1, 2, 3, 4, 5, 6, 7]);
+            read_id_regs!(regs, 2, [0, 1, 2, 3, 4, 5, 6, 7]);
+            read_id_regs!(regs, 3, [0, 1, 2, 3, 4, 5, 6, 7]);
+            read_id_regs!(regs, 4, [0, 1, 2, 3, 4, 5, 6, 7]);
+            read_id_regs!(regs, 5, [0, 1, 2, 3, 4, 5, 6, 7]);
+            read_id_regs!(regs, 6, [0, 1, 2, 3, 4, 5, 6, 7]);
+            read_id_regs!(regs, 7, [0, 1, 2, 3, 4, 5, 6, 7]);
+        }
+        Self { regs }
+    }
+
+    /// Returns the value of the ID register `addr`, `None` if `addr` is not an ID register.
+    pub fn get(&self, addr: SysRegAddr) -> Option<u64> {
+        Self::index(addr).map(|i| self.regs[i])
+    }
+
+    /// Sets the value of the ID register `addr`.
+    ///
+    /// Returns `false` if `addr` is not an ID register.
+    pub fn set(&mut self, addr: SysRegAddr, value: u64) -> bool {
+        match Self::index(addr) {
+            Some(i) => {
+                self.regs[i] = value;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Limits the unsigned 4-bit feature field at bits `[shift + 3:shift]` of the ID register
+    /// `addr` to `max`, i.e., hides the features beyond level `max`.
+    ///
+    /// Signed fields, such as `ID_AA64PFR0_EL1.FP`, where `0b1111` means not implemented, should
+    /// be changed with [`Self::set`] instead.
+    pub fn limit_feature(&mut self, addr: SysRegAddr, shift: u32, max: u64) -> bool {
+        let Some(i) = Self::index(addr) else {
+            return false;
+        };
+        let field = (self.regs[i] >> shift) & 0xf;
+        if field > max {
+            self.regs[i] = (self.regs[i] & !(0xf << shift)) | ((max & 0xf) << shift);
+        }
+        true
+    }
+
+    /// Limits every feature field to the lower of this view and `other`, as unsigned 4-bit
+    /// fields, e.g. to get the features common to two kinds of cores.
+    ///
+    /// Signed fields are not handled specially, and should be fixed up with [`Self::set`].
+    pub fn intersect(&mut self, other: &Self) {
+        for (reg, other) in self.regs.iter_mut().zip(other.regs.iter()) {
+            let mut value = 0;
+            for shift in (0..64).step_by(4) {
+                let field = ((*reg >> shift) & 0xf).min((other >> shift) & 0xf);
+                value |= field << shift;
+            }
+            *reg = value;
+        }
+    }
+}
+
+impl Default for IdRegisters {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -15,22 +15,57 @@
 #![no_std]
 #![feature(doc_cfg)]
 #![doc = include_str!("../README.md")]
+// Only the architecture-independent modules are built for other targets, to run their unit
+// tests on the host; most of their items are used by the vCPU alone.
+#![cfg_attr(not(target_arch = "aarch64"), allow(dead_code))]
 
-#[macro_use]
+#[cfg_attr(target_arch = "aarch64", macro_use)]
 extern crate log;
 
+mod cache;
+#[cfg(target_arch = "aarch64")]
 mod context_frame;
+mod decode;
+#[cfg(target_arch = "aarch64")]
 #[macro_use]
 mod exception_utils;
+#[cfg(target_arch = "aarch64")]
 mod exception;
+mod exit;
+mod id_regs;
+#[cfg(target_arch = "aarch64")]
 mod pcpu;
+mod regs;
+mod sgi;
+#[cfg(target_arch = "aarch64")]
 mod smc;
+mod snapshot;
+mod timer;
+mod trap;
+#[cfg(target_arch = "aarch64")]
 mod vcpu;
 
+#[cfg(target_arch = "aarch64")]
+pub use self::context_frame::{
+    FpSimdRegisters, GicV3Registers, GuestSystemRegisters, ICH_LR_MAX, VirtualIrqState,
+};
+pub use self::exit::{Aarch64ExitDetail, Endianness, FaultAccess, SErrorSeverity, Stage2FaultKind};
+pub use self::id_regs::IdRegisters;
+#[cfg(target_arch = "aarch64")]
 pub use self::pcpu::Aarch64PerCpu;
-pub use self::vcpu::{Aarch64VCpu, Aarch64VCpuCreateConfig, Aarch64VCpuSetupConfig};
+pub use self::regs::VCpuReg;
+pub use self::sgi::{MPIDR_AFFINITY_MASK, affinity_to_vcpu_id, sgi_target_affinities};
+#[cfg(target_arch = "aarch64")]
+pub use self::smc::{SmcFallback, SmcPolicy};
+pub use self::snapshot::VCpuSnapshot;
+pub use self::trap::TrapPolicy;
+#[cfg(target_arch = "aarch64")]
+pub use self::vcpu::{
+    Aarch64VCpu, Aarch64VCpuCreateConfig, Aarch64VCpuSetupConfig, VmCpuRegisters,
+};
 
 /// context frame for aarch64
+#[cfg(target_arch = "aarch64")]
 pub type TrapFrame = context_frame::Aarch64ContextFrame;
 
 /// Return if current platform support virtualization extension.
@@ -76,7 +76,8 @@ impl<H: AxVCpuHal> AxArchPerCpu for Aarch64PerCpu<H> {
         //
         // `ICH_HCR_EL2[0]` controls the virtual CPU interface operation.
         //
-        // We leave it for the virtual GIC implementations to decide whether to enable it or not.
+        // We leave it for the virtual GIC implementations to decide whether to enable it or not,
+        // or to `Aarch64VCpu` if `Aarch64VCpuSetupConfig::gicv3_context` is set.
         //
         // unsafe {
         //     core::arch::asm! {
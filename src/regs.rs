@@ -0,0 +1,135 @@
+// Copyright 2025 The Axvisor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/// A register of the guest saved by the vCPU, accessed with [`Aarch64VCpu::reg`] and
+/// [`Aarch64VCpu::set_reg`].
+///
+/// 32-bit registers read as zero-extended, and only their low 32 bits are written. The
+/// SIMD&FP `V` registers are accessed with [`Aarch64VCpu::fp_regs`], and the GICv3 virtual CPU
+/// interface with [`Aarch64VCpu::gic_regs`].
+///
+/// [`Aarch64VCpu::reg`]: crate::Aarch64VCpu::reg
+/// [`Aarch64VCpu::set_reg`]: crate::Aarch64VCpu::set_reg
+/// [`Aarch64VCpu::fp_regs`]: crate::Aarch64VCpu::fp_regs
+/// [`Aarch64VCpu::gic_regs`]: crate::Aarch64VCpu::gic_regs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum VCpuReg {
+    /// The general-purpose register `Xn`, `n` in `0..=30`. For AArch32 guests, the AArch32
+    /// registers are mapped to them as architecturally defined, e.g. `X13` and `X14` are `SP_usr`
+    /// and `LR_usr`.
+    X(usize),
+    /// The PC of the guest, where it resumes, i.e., `ELR_EL2`.
+    Pc,
+    /// The PSTATE of the guest, in the `SPSR_EL2` format.
+    Pstate,
+    /// `SP_EL0`.
+    SpEl0,
+    /// `SP_EL1`.
+    SpEl1,
+    /// `ELR_EL1`.
+    ElrEl1,
+    /// `SPSR_EL1`, `SPSR_svc` for AArch32 guests.
+    SpsrEl1,
+    /// `SCTLR_EL1`.
+    SctlrEl1,
+    /// `ACTLR_EL1`.
+    ActlrEl1,
+    /// `CPACR_EL1`.
+    CpacrEl1,
+    /// `TTBR0_EL1`.
+    Ttbr0El1,
+    /// `TTBR1_EL1`.
+    Ttbr1El1,
+    /// `TCR_EL1`.
+    TcrEl1,
+    /// `ESR_EL1`.
+    EsrEl1,
+    /// `AFSR0_EL1`, `ADFSR` of AArch32 guests in its low 32 bits.
+    Afsr0El1,
+    /// `AFSR1_EL1`, `AIFSR` of AArch32 guests in its low 32 bits.
+    Afsr1El1,
+    /// `FAR_EL1`.
+    FarEl1,
+    /// `PAR_EL1`.
+    ParEl1,
+    /// `MAIR_EL1`.
+    MairEl1,
+    /// `AMAIR_EL1`.
+    AmairEl1,
+    /// `VBAR_EL1`.
+    VbarEl1,
+    /// `CONTEXTIDR_EL1`.
+    ContextidrEl1,
+    /// `TPIDR_EL0`.
+    TpidrEl0,
+    /// `TPIDR_EL1`.
+    TpidrEl1,
+    /// `TPIDRRO_EL0`.
+    TpidrroEl0,
+    /// `CNTKCTL_EL1`.
+    CntkctlEl1,
+    /// `CNTV_CTL_EL0`.
+    CntvCtlEl0,
+    /// `CNTV_CVAL_EL0`.
+    CntvCvalEl0,
+    /// `CNTP_CTL_EL0`, only switched if the EL1 physical timer is passed through.
+    CntpCtlEl0,
+    /// `CNTP_CVAL_EL0`, only switched if the EL1 physical timer is passed through.
+    CntpCvalEl0,
+    /// `CNTVOFF_EL2`, the offset of the virtual counter of the guest.
+    CntvoffEl2,
+    /// `VPIDR_EL2`, the `MIDR_EL1` value the guest reads.
+    VpidrEl2,
+    /// `VMPIDR_EL2`, the `MPIDR_EL1` value the guest reads.
+    VmpidrEl2,
+    /// `SPSR_abt` of AArch32 guests.
+    SpsrAbt,
+    /// `SPSR_und` of AArch32 guests.
+    SpsrUnd,
+    /// `SPSR_irq` of AArch32 guests.
+    SpsrIrq,
+    /// `SPSR_fiq` of AArch32 guests.
+    SpsrFiq,
+    /// `DACR32_EL2`, `DACR` of AArch32 guests.
+    Dacr32El2,
+    /// `IFSR32_EL2`, `IFSR` of AArch32 guests.
+    Ifsr32El2,
+    /// `FPEXC32_EL2`, `FPEXC` of AArch32 guests.
+    Fpexc32El2,
+    /// `FPSR`.
+    Fpsr,
+    /// `FPCR`.
+    Fpcr,
+    /// `PMCR_EL0`.
+    PmcrEl0,
+    /// `HCR_EL2` of the vCPU. Changing it overrides the setup of the vCPU, e.g. the trap policy.
+    HcrEl2,
+    /// `VTTBR_EL2` of the vCPU, see [`AxArchVCpu::set_ept_root`].
+    ///
+    /// [`AxArchVCpu::set_ept_root`]: axvcpu::AxArchVCpu::set_ept_root
+    VttbrEl2,
+    /// `VTCR_EL2` of the vCPU.
+    VtcrEl2,
+    /// `CPTR_EL2` of the vCPU.
+    CptrEl2,
+    /// `HSTR_EL2` of the vCPU.
+    HstrEl2,
+    /// `MDCR_EL2` of the vCPU.
+    MdcrEl2,
+    /// `CNTHCTL_EL2` of the vCPU.
+    CnthctlEl2,
+    /// `VSESR_EL2`, the syndrome of a pending virtual SError.
+    VsesrEl2,
+}
@@ -0,0 +1,94 @@
+// Copyright 2025 The Axvisor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Decoding of the GICv3 SGI generation registers, `ICC_SGI0R_EL1`, `ICC_SGI1R_EL1` and
+//! `ICC_ASGI1R_EL1`, into [`AxVCpuExitReason::SendIPI`] exits.
+
+use axvcpu::AxVCpuExitReason;
+
+/// The affinity fields of `MPIDR_EL1`, `Aff3` (bits \[39:32\]) and `Aff2`, `Aff1`, `Aff0`
+/// (bits \[23:0\]).
+pub const MPIDR_AFFINITY_MASK: u64 = 0xff_00ff_ffff;
+
+/// `ICC_SGI1R_EL1.TargetList`, bits \[15:0\].
+const SGIR_TARGET_LIST_MASK: u64 = 0xffff;
+/// `ICC_SGI1R_EL1.Aff1`, bits \[23:16\].
+const SGIR_AFF1_SHIFT: u64 = 16;
+/// `ICC_SGI1R_EL1.INTID`, bits \[27:24\].
+const SGIR_INTID_SHIFT: u64 = 24;
+/// `ICC_SGI1R_EL1.Aff2`, bits \[39:32\].
+const SGIR_AFF2_SHIFT: u64 = 32;
+/// `ICC_SGI1R_EL1.IRM`, routes the SGI to all PEs but the sender if set.
+const SGIR_IRM: u64 = 1 << 40;
+/// `ICC_SGI1R_EL1.RS`, bits \[47:44\], `Aff0` of the targets is `RS * 16 + <bit in TargetList>`.
+const SGIR_RS_SHIFT: u64 = 44;
+/// `ICC_SGI1R_EL1.Aff3`, bits \[55:48\].
+const SGIR_AFF3_SHIFT: u64 = 48;
+
+/// Decodes a write of `value` to `ICC_SGI0R_EL1`, `ICC_SGI1R_EL1` or `ICC_ASGI1R_EL1`, which
+/// share the same layout.
+///
+/// For an SGI routed to the PEs in `TargetList`, `target_cpu` of the exit is the MPIDR affinity
+/// of the PE for bit 0 of `TargetList`, i.e., with `Aff0` equal to `RS * 16`, and
+/// `target_cpu_aux` is `TargetList`, see [`sgi_target_affinities`]. If `TargetList` is empty,
+/// no SGI is generated and `Nothing` is returned.
+pub(crate) fn decode_sgi_register(value: u64) -> AxVCpuExitReason {
+    let intid = (value >> SGIR_INTID_SHIFT) & 0b1111;
+
+    if value & SGIR_IRM != 0 {
+        return AxVCpuExitReason::SendIPI {
+            target_cpu: 0,
+            target_cpu_aux: 0,
+            send_to_all: true,
+            send_to_self: false,
+            vector: intid,
+        };
+    }
+
+    let target_list = value & SGIR_TARGET_LIST_MASK;
+    if target_list == 0 {
+        return AxVCpuExitReason::Nothing;
+    }
+
+    let aff3 = (value >> SGIR_AFF3_SHIFT) & 0xff;
+    let aff2 = (value >> SGIR_AFF2_SHIFT) & 0xff;
+    let aff1 = (value >> SGIR_AFF1_SHIFT) & 0xff;
+    let aff0 = ((value >> SGIR_RS_SHIFT) & 0xf) * 16;
+
+    AxVCpuExitReason::SendIPI {
+        target_cpu: (aff3 << 32) | (aff2 << 16) | (aff1 << 8) | aff0,
+        target_cpu_aux: target_list,
+        send_to_all: false,
+        send_to_self: false,
+        vector: intid,
+    }
+}
+
+/// Returns the MPIDR affinities of the target PEs of a [`AxVCpuExitReason::SendIPI`] exit that
+/// is not sent to all PEs, from its `target_cpu` and `target_cpu_aux`.
+pub fn sgi_target_affinities(target_cpu: u64, target_cpu_aux: u64) -> impl Iterator<Item = u64> {
+    (0..16)
+        .filter(move |bit| target_cpu_aux & (1 << bit) != 0)
+        .map(move |bit| (target_cpu & MPIDR_AFFINITY_MASK) + bit)
+}
+
+/// Maps the MPIDR affinity `affinity` to the ID of the vCPU with that affinity, given the
+/// `MPIDR_EL1` values of the vCPUs of a VM, indexed by vCPU ID.
+///
+/// Only the affinity fields are compared, see [`MPIDR_AFFINITY_MASK`].
+pub fn affinity_to_vcpu_id(vcpu_mpidrs: &[u64], affinity: u64) -> Option<usize> {
+    vcpu_mpidrs
+        .iter()
+        .position(|mpidr| mpidr & MPIDR_AFFINITY_MASK == affinity & MPIDR_AFFINITY_MASK)
+}
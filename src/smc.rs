@@ -13,6 +13,9 @@
 // limitations under the License.
 
 use core::arch::asm;
+use core::cell::OnceCell;
+
+use aarch64_cpu::registers::{ID_AA64PFR0_EL1, ID_AA64PFR1_EL1, Readable};
 
 #[inline(never)]
 /// invoke a secure monitor call
@@ -38,3 +41,166 @@ pub unsafe fn smc_call(x0: u64, x1: u64, x2: u64, x3: u64) -> (u64, u64, u64, u6
     }
     (r0, r1, r2, r3)
 }
+
+/// invoke a secure monitor call with the full register set of SMC Calling Convention v1.2
+///
+/// Arguments are passed in `x0`-`x17`, and results are returned in `x0`-`x17` as well.
+/// # Safety:
+/// Same as [`smc_call`].
+#[inline(never)]
+pub unsafe fn smc_call_v1_2(regs: &mut [u64; 18]) {
+    unsafe {
+        asm!(
+            "smc #0",
+            inout("x0") regs[0],
+            inout("x1") regs[1],
+            inout("x2") regs[2],
+            inout("x3") regs[3],
+            inout("x4") regs[4],
+            inout("x5") regs[5],
+            inout("x6") regs[6],
+            inout("x7") regs[7],
+            inout("x8") regs[8],
+            inout("x9") regs[9],
+            inout("x10") regs[10],
+            inout("x11") regs[11],
+            inout("x12") regs[12],
+            inout("x13") regs[13],
+            inout("x14") regs[14],
+            inout("x15") regs[15],
+            inout("x16") regs[16],
+            inout("x17") regs[17],
+            options(nomem, nostack)
+        );
+    }
+}
+
+/// What to do with a guest SMC call that is not emulated by the vCPU and not in
+/// [`SmcPolicy::forward`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SmcFallback {
+    /// Return `NOT_SUPPORTED` (-1) to the guest.
+    #[default]
+    Deny,
+    /// Forward the call to the firmware.
+    Forward,
+    /// Exit to the VMM to emulate the call, see [`Aarch64ExitDetail::SmcCall`](crate::Aarch64ExitDetail::SmcCall).
+    Exit,
+}
+
+/// The policy for guest SMC calls that are not emulated by the vCPU.
+///
+/// PSCI calls are always emulated by the vCPU and never reach the policy.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SmcPolicy {
+    /// Function IDs of the SMC calls forwarded to the firmware.
+    ///
+    /// Forwarded calls pass `x0`-`x17` to the firmware and back, as SMCCC v1.2 defines, or only
+    /// `r0`-`r7` for AArch32 guests.
+    pub forward: &'static [u32],
+    /// What to do with the other SMC calls, denied by default.
+    pub fallback: SmcFallback,
+}
+
+impl SmcPolicy {
+    /// Forward all SMC calls to the firmware.
+    pub const FORWARD_ALL: Self = Self {
+        forward: &[],
+        fallback: SmcFallback::Forward,
+    };
+}
+
+/// `SMCCC_VERSION`, available since SMCCC v1.1.
+pub(crate) const SMCCC_VERSION: u32 = 0x8000_0000;
+/// `SMCCC_ARCH_FEATURES`, available since SMCCC v1.1.
+pub(crate) const SMCCC_ARCH_FEATURES: u32 = 0x8000_0001;
+/// `SMCCC_ARCH_SOC_ID`, available since SMCCC v1.2.
+pub(crate) const SMCCC_ARCH_SOC_ID: u32 = 0x8000_0002;
+/// `SMCCC_ARCH_WORKAROUND_1`, the firmware mitigation for Spectre variant 2 (CVE-2017-5715).
+pub(crate) const SMCCC_ARCH_WORKAROUND_1: u32 = 0x8000_8000;
+/// `SMCCC_ARCH_WORKAROUND_2`, the firmware mitigation for Spectre variant 4 (CVE-2018-3639).
+pub(crate) const SMCCC_ARCH_WORKAROUND_2: u32 = 0x8000_7FFF;
+/// `SMCCC_ARCH_WORKAROUND_3`, the firmware mitigation for Spectre-BHB (CVE-2022-23960).
+pub(crate) const SMCCC_ARCH_WORKAROUND_3: u32 = 0x8000_3FFF;
+
+/// The state of the current CPU regarding one of the `SMCCC_ARCH_WORKAROUND_*` calls.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum WorkaroundState {
+    /// The CPU is affected, and the firmware does not provide the workaround.
+    Vulnerable,
+    /// The CPU is affected, and the firmware provides the workaround.
+    Mitigated,
+    /// The CPU is not affected.
+    Unaffected,
+}
+
+/// The states of the current CPU regarding all `SMCCC_ARCH_WORKAROUND_*` calls.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct HostWorkarounds {
+    pub wa1: WorkaroundState,
+    pub wa2: WorkaroundState,
+    pub wa3: WorkaroundState,
+    /// Whether the CPU implements `PSTATE.SSBS`, with which software controls the Spectre
+    /// variant 4 mitigation by itself.
+    pub ssbs: bool,
+}
+
+/// Workaround states of the current CPU, probed on first use.
+///
+/// Probed per CPU, as firmware reports the states of the calling PE, which may differ between
+/// the clusters of a big.LITTLE system.
+#[percpu::def_percpu]
+static HOST_WORKAROUNDS: OnceCell<HostWorkarounds> = OnceCell::new();
+
+/// Returns the `SMCCC_ARCH_WORKAROUND_*` states of the current CPU.
+pub(crate) fn host_workarounds() -> HostWorkarounds {
+    *unsafe { HOST_WORKAROUNDS.current_ref_raw() }.get_or_init(probe_host_workarounds)
+}
+
+fn probe_host_workarounds() -> HostWorkarounds {
+    // Discovery through `SMCCC_ARCH_FEATURES` requires SMCCC v1.1.
+    let (version, ..) = unsafe { smc_call(SMCCC_VERSION as u64, 0, 0, 0) };
+    let has_arch_features = (version as i32) >= 0x1_0001;
+    let arch_features = |fn_id: u32| -> i32 {
+        if has_arch_features {
+            unsafe { smc_call(SMCCC_ARCH_FEATURES as u64, fn_id as u64, 0, 0) }.0 as i32
+        } else {
+            -1
+        }
+    };
+
+    // ID_AA64PFR0_EL1.CSV2: branch targets trained in other contexts can not affect speculation.
+    let csv2 = (ID_AA64PFR0_EL1.get() >> 56) & 0xf != 0;
+    let wa1 = if csv2 {
+        WorkaroundState::Unaffected
+    } else {
+        match arch_features(SMCCC_ARCH_WORKAROUND_1) {
+            0 => WorkaroundState::Mitigated,
+            1 => WorkaroundState::Unaffected,
+            _ => WorkaroundState::Vulnerable,
+        }
+    };
+    let wa2 = match arch_features(SMCCC_ARCH_WORKAROUND_2) {
+        // Either the mitigation is dynamic and enabled by default, which the host never turns
+        // off, or it is permanently enabled.
+        0 | -2 => WorkaroundState::Mitigated,
+        1 => WorkaroundState::Unaffected,
+        _ => WorkaroundState::Vulnerable,
+    };
+    let wa3 = match arch_features(SMCCC_ARCH_WORKAROUND_3) {
+        0 => WorkaroundState::Mitigated,
+        1 => WorkaroundState::Unaffected,
+        _ => WorkaroundState::Vulnerable,
+    };
+
+    // ID_AA64PFR1_EL1.SSBS
+    let ssbs = (ID_AA64PFR1_EL1.get() >> 4) & 0xf != 0;
+
+    debug!("SMCCC arch workarounds: {wa1:?}, {wa2:?}, {wa3:?}, SSBS: {ssbs}");
+    HostWorkarounds {
+        wa1,
+        wa2,
+        wa3,
+        ssbs,
+    }
+}
@@ -0,0 +1,446 @@
+// Copyright 2025 The Axvisor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Snapshots of the architectural state of a vCPU, for checkpointing and live migration.
+//!
+//! The binary format is a header followed by a sequence of records, all little-endian:
+//!
+//! ```text
+//! header: magic "AVCS" (4 bytes) | version: u16 | reserved: u16
+//! record: tag: u16 | len: u16 | value: [u8; len]
+//! ```
+//!
+//! Each register is a record of its own, so that registers can be added in later versions
+//! without breaking older readers, which skip the tags they do not know. The version is only
+//! bumped for incompatible changes.
+
+use axerrno::{AxResult, ax_err};
+
+use crate::regs::VCpuReg;
+
+/// Tags of the records.
+const TAG_MPIDR: u16 = 0x0001;
+const TAG_AARCH32: u16 = 0x0002;
+const TAG_PC: u16 = 0x0010;
+const TAG_PSTATE: u16 = 0x0011;
+const TAG_SP_EL0: u16 = 0x0012;
+/// `X0`-`X30`, `TAG_GPR + n`.
+const TAG_GPR: u16 = 0x0100;
+/// [`VCpuSnapshot::SYS_REGS`], `TAG_SYS_REG + index`.
+const TAG_SYS_REG: u16 = 0x0200;
+const TAG_CNTKCTL_EL1: u16 = 0x0300;
+const TAG_CNTV_CTL_EL0: u16 = 0x0301;
+const TAG_CNTV_CVAL_EL0: u16 = 0x0302;
+const TAG_CNTP_CTL_EL0: u16 = 0x0303;
+const TAG_CNTP_CVAL_EL0: u16 = 0x0304;
+const TAG_CNTVOFF_EL2: u16 = 0x0305;
+const TAG_HOST_COUNTER: u16 = 0x0306;
+/// `V0`-`V31`, `TAG_VREG + n`.
+const TAG_VREG: u16 = 0x0400;
+const TAG_FPSR: u16 = 0x0480;
+const TAG_FPCR: u16 = 0x0481;
+
+/// The size of the header.
+const HEADER_LEN: usize = 8;
+/// The size of the tag and length of a record.
+const RECORD_HEADER_LEN: usize = 4;
+/// The number of system registers in a snapshot.
+const NR_SYS_REGS: usize = 29;
+/// The number of records of version 1, all of which must be present.
+const NR_V1_RECORDS: usize = 5 + 31 + NR_SYS_REGS + 7 + 32 + 2;
+
+/// The architectural state of a vCPU, taken with [`Aarch64VCpu::snapshot`] and installed with
+/// [`Aarch64VCpu::restore`].
+///
+/// This covers the general-purpose, system, timer and FP/SIMD registers of the guest and its
+/// MPIDR, but not the EL2 controls of the vCPU, which are set up by the VMM on the destination,
+/// nor the GICv3 virtual CPU interface state, see [`Aarch64VCpu::regs`], nor the SVE registers,
+/// so vCPUs with SVE enabled can not be snapshotted. The snapshot can be
+/// encoded with [`Self::to_bytes`], or with serde if the `serde` feature is enabled.
+///
+/// [`Aarch64VCpu::snapshot`]: crate::Aarch64VCpu::snapshot
+/// [`Aarch64VCpu::restore`]: crate::Aarch64VCpu::restore
+/// [`Aarch64VCpu::regs`]: crate::Aarch64VCpu::regs
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct VCpuSnapshot {
+    /// The `MPIDR_EL1` value of the vCPU.
+    pub mpidr: u64,
+    /// Whether EL1 of the guest runs in AArch32 state.
+    pub aarch32: bool,
+    /// `X0`-`X30`.
+    pub gpr: [u64; 31],
+    /// `SP_EL0`.
+    pub sp_el0: u64,
+    /// The PC where the guest resumes.
+    pub pc: u64,
+    /// The PSTATE of the guest, in the `SPSR_EL2` format.
+    pub pstate: u64,
+    /// The system registers, in the order of [`Self::SYS_REGS`].
+    pub sys_regs: [u64; NR_SYS_REGS],
+    /// `CNTKCTL_EL1`.
+    pub cntkctl_el1: u64,
+    /// `CNTV_CTL_EL0`.
+    pub cntv_ctl_el0: u64,
+    /// `CNTV_CVAL_EL0`.
+    pub cntv_cval_el0: u64,
+    /// `CNTP_CTL_EL0`, whether the EL1 physical timer is passed through or emulated.
+    pub cntp_ctl_el0: u64,
+    /// `CNTP_CVAL_EL0`, whether the EL1 physical timer is passed through or emulated.
+    pub cntp_cval_el0: u64,
+    /// `CNTVOFF_EL2`, the offset of the guest counter from the host counter.
+    pub cntvoff_el2: u64,
+    /// The host counter (`CNTPCT_EL0`) when the snapshot was taken.
+    pub host_counter: u64,
+    /// The SIMD&FP registers `V0`-`V31`.
+    pub vregs: [u128; 32],
+    /// `FPSR`.
+    pub fpsr: u64,
+    /// `FPCR`.
+    pub fpcr: u64,
+}
+
+impl VCpuSnapshot {
+    /// The magic number at the start of an encoded snapshot.
+    pub const MAGIC: [u8; 4] = *b"AVCS";
+    /// The version of the binary format.
+    pub const VERSION: u16 = 1;
+    /// The system registers of a snapshot, in the order of [`Self::sys_regs`].
+    ///
+    /// Registers are only ever appended to this list, as their index is their tag.
+    pub const SYS_REGS: [VCpuReg; NR_SYS_REGS] = [
+        VCpuReg::SpEl1,
+        VCpuReg::ElrEl1,
+        VCpuReg::SpsrEl1,
+        VCpuReg::SctlrEl1,
+        VCpuReg::ActlrEl1,
+        VCpuReg::CpacrEl1,
+        VCpuReg::Ttbr0El1,
+        VCpuReg::Ttbr1El1,
+        VCpuReg::TcrEl1,
+        VCpuReg::EsrEl1,
+        VCpuReg::FarEl1,
+        VCpuReg::ParEl1,
+        VCpuReg::MairEl1,
+        VCpuReg::AmairEl1,
+        VCpuReg::VbarEl1,
+        VCpuReg::ContextidrEl1,
+        VCpuReg::TpidrEl0,
+        VCpuReg::TpidrEl1,
+        VCpuReg::TpidrroEl0,
+        VCpuReg::PmcrEl0,
+        VCpuReg::SpsrAbt,
+        VCpuReg::SpsrUnd,
+        VCpuReg::SpsrIrq,
+        VCpuReg::SpsrFiq,
+        VCpuReg::Dacr32El2,
+        VCpuReg::Ifsr32El2,
+        VCpuReg::Fpexc32El2,
+        VCpuReg::Afsr0El1,
+        VCpuReg::Afsr1El1,
+    ];
+    /// The size of a snapshot encoded by [`Self::to_bytes`].
+    pub const ENCODED_LEN: usize = HEADER_LEN
+        // MPIDR, AArch32, PC, PSTATE and SP_EL0.
+        + (RECORD_HEADER_LEN + 8) * 4
+        + (RECORD_HEADER_LEN + 1)
+        + (RECORD_HEADER_LEN + 8) * (31 + NR_SYS_REGS + 7 + 2)
+        + (RECORD_HEADER_LEN + 16) * 32;
+
+    /// Returns the value of the system register `reg` in the snapshot, `None` if it is not
+    /// part of snapshots.
+    pub fn sys_reg(&self, reg: VCpuReg) -> Option<u64> {
+        let index = Self::SYS_REGS.iter().position(|r| *r == reg)?;
+        Some(self.sys_regs[index])
+    }
+
+    /// Returns the guest counter when the snapshot was taken.
+    pub fn guest_counter(&self) -> u64 {
+        self.host_counter.wrapping_sub(self.cntvoff_el2)
+    }
+
+    /// Returns the `CNTVOFF_EL2` to restore the snapshot with on a host whose counter reads
+    /// `host_counter`, so that the guest counter resumes from [`Self::guest_counter`], as if the
+    /// VM had been paused in between.
+    ///
+    /// All vCPUs of a VM must share the same offset, so it should be computed once, e.g. from
+    /// the last snapshot taken, and set to [`Self::cntvoff_el2`] of all the snapshots of the VM.
+    pub fn counter_offset_for_host(&self, host_counter: u64) -> u64 {
+        host_counter.wrapping_sub(self.guest_counter())
+    }
+
+    /// Encodes the snapshot into `buf`, returning the number of bytes written, which is
+    /// [`Self::ENCODED_LEN`].
+    ///
+    /// Returns [`AxError::NoMemory`](axerrno::AxError::NoMemory) if `buf` is too small.
+    pub fn to_bytes(&self, buf: &mut [u8]) -> AxResult<usize> {
+        if buf.len() < Self::ENCODED_LEN {
+            return ax_err!(NoMemory, "buffer too small for the vCPU snapshot");
+        }
+
+        let mut writer = Writer { buf, pos: 0 };
+        writer.put(&Self::MAGIC);
+        writer.put(&Self::VERSION.to_le_bytes());
+        writer.put(&0u16.to_le_bytes());
+
+        writer.record(TAG_MPIDR, &self.mpidr.to_le_bytes());
+        writer.record(TAG_AARCH32, &[self.aarch32 as u8]);
+        writer.record(TAG_PC, &self.pc.to_le_bytes());
+        writer.record(TAG_PSTATE, &self.pstate.to_le_bytes());
+        writer.record(TAG_SP_EL0, &self.sp_el0.to_le_bytes());
+        for (i, value) in self.gpr.iter().enumerate() {
+            writer.record(TAG_GPR + i as u16, &value.to_le_bytes());
+        }
+        for (i, value) in self.sys_regs.iter().enumerate() {
+            writer.record(TAG_SYS_REG + i as u16, &value.to_le_bytes());
+        }
+        writer.record(TAG_CNTKCTL_EL1, &self.cntkctl_el1.to_le_bytes());
+        writer.record(TAG_CNTV_CTL_EL0, &self.cntv_ctl_el0.to_le_bytes());
+        writer.record(TAG_CNTV_CVAL_EL0, &self.cntv_cval_el0.to_le_bytes());
+        writer.record(TAG_CNTP_CTL_EL0, &self.cntp_ctl_el0.to_le_bytes());
+        writer.record(TAG_CNTP_CVAL_EL0, &self.cntp_cval_el0.to_le_bytes());
+        writer.record(TAG_CNTVOFF_EL2, &self.cntvoff_el2.to_le_bytes());
+        writer.record(TAG_HOST_COUNTER, &self.host_counter.to_le_bytes());
+        for (i, value) in self.vregs.iter().enumerate() {
+            writer.record(TAG_VREG + i as u16, &value.to_le_bytes());
+        }
+        writer.record(TAG_FPSR, &self.fpsr.to_le_bytes());
+        writer.record(TAG_FPCR, &self.fpcr.to_le_bytes());
+
+        Ok(writer.pos)
+    }
+
+    /// Decodes a snapshot encoded by [`Self::to_bytes`].
+    ///
+    /// Records with unknown tags are skipped. Returns
+    /// [`AxError::InvalidData`](axerrno::AxError::InvalidData) if `bytes` is not a snapshot, is
+    /// truncated, lacks a record of version 1, or has an unsupported version.
+    pub fn from_bytes(bytes: &[u8]) -> AxResult<Self> {
+        if bytes.len() < HEADER_LEN || bytes[..4] != Self::MAGIC {
+            return ax_err!(InvalidData, "not a vCPU snapshot");
+        }
+        let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+        if version == 0 || version > Self::VERSION {
+            return ax_err!(InvalidData, "unsupported vCPU snapshot version");
+        }
+
+        let mut snapshot = Self::default();
+        let mut seen = 0u128;
+        let mut rest = &bytes[HEADER_LEN..];
+        while !rest.is_empty() {
+            if rest.len() < RECORD_HEADER_LEN {
+                return ax_err!(InvalidData, "truncated vCPU snapshot");
+            }
+            let tag = u16::from_le_bytes([rest[0], rest[1]]);
+            let len = u16::from_le_bytes([rest[2], rest[3]]) as usize;
+            let Some(value) = rest.get(RECORD_HEADER_LEN..RECORD_HEADER_LEN + len) else {
+                return ax_err!(InvalidData, "truncated vCPU snapshot");
+            };
+            snapshot.decode_record(tag, value)?;
+            if let Some(index) = v1_record_index(tag) {
+                seen |= 1 << index;
+            }
+            rest = &rest[RECORD_HEADER_LEN + len..];
+        }
+        if seen != (1 << NR_V1_RECORDS) - 1 {
+            return ax_err!(InvalidData, "incomplete vCPU snapshot");
+        }
+        Ok(snapshot)
+    }
+
+    /// Decodes the record `tag` with `value` into the snapshot.
+    fn decode_record(&mut self, tag: u16, value: &[u8]) -> AxResult {
+        let gprs = TAG_GPR..TAG_GPR + 31;
+        let sys_regs = TAG_SYS_REG..TAG_SYS_REG + NR_SYS_REGS as u16;
+        let vregs = TAG_VREG..TAG_VREG + 32;
+
+        let field = match tag {
+            TAG_AARCH32 => {
+                let [value] = value else {
+                    return ax_err!(InvalidData, "invalid vCPU snapshot record");
+                };
+                self.aarch32 = *value != 0;
+                return Ok(());
+            }
+            _ if vregs.contains(&tag) => {
+                let Ok(value) = value.try_into() else {
+                    return ax_err!(InvalidData, "invalid vCPU snapshot record");
+                };
+                self.vregs[(tag - TAG_VREG) as usize] = u128::from_le_bytes(value);
+                return Ok(());
+            }
+            TAG_MPIDR => &mut self.mpidr,
+            TAG_PC => &mut self.pc,
+            TAG_PSTATE => &mut self.pstate,
+            TAG_SP_EL0 => &mut self.sp_el0,
+            _ if gprs.contains(&tag) => &mut self.gpr[(tag - TAG_GPR) as usize],
+            _ if sys_regs.contains(&tag) => &mut self.sys_regs[(tag - TAG_SYS_REG) as usize],
+            TAG_CNTKCTL_EL1 => &mut self.cntkctl_el1,
+            TAG_CNTV_CTL_EL0 => &mut self.cntv_ctl_el0,
+            TAG_CNTV_CVAL_EL0 => &mut self.cntv_cval_el0,
+            TAG_CNTP_CTL_EL0 => &mut self.cntp_ctl_el0,
+            TAG_CNTP_CVAL_EL0 => &mut self.cntp_cval_el0,
+            TAG_CNTVOFF_EL2 => &mut self.cntvoff_el2,
+            TAG_HOST_COUNTER => &mut self.host_counter,
+            TAG_FPSR => &mut self.fpsr,
+            TAG_FPCR => &mut self.fpcr,
+            // Registers added by later versions of the format.
+            _ => return Ok(()),
+        };
+        let Ok(value) = value.try_into() else {
+            return ax_err!(InvalidData, "invalid vCPU snapshot record");
+        };
+        *field = u64::from_le_bytes(value);
+        Ok(())
+    }
+}
+
+/// Returns the index of the record `tag` among the records of version 1, `None` for the tags
+/// added by later versions, which may be missing.
+fn v1_record_index(tag: u16) -> Option<usize> {
+    let gprs = TAG_GPR..TAG_GPR + 31;
+    let sys_regs = TAG_SYS_REG..TAG_SYS_REG + NR_SYS_REGS as u16;
+    let timers = TAG_CNTKCTL_EL1..TAG_HOST_COUNTER + 1;
+    let vregs = TAG_VREG..TAG_VREG + 32;
+
+    let index = match tag {
+        TAG_MPIDR => 0,
+        TAG_AARCH32 => 1,
+        TAG_PC => 2,
+        TAG_PSTATE => 3,
+        TAG_SP_EL0 => 4,
+        _ if gprs.contains(&tag) => 5 + (tag - TAG_GPR) as usize,
+        _ if sys_regs.contains(&tag) => 36 + (tag - TAG_SYS_REG) as usize,
+        _ if timers.contains(&tag) => 36 + NR_SYS_REGS + (tag - TAG_CNTKCTL_EL1) as usize,
+        _ if vregs.contains(&tag) => 43 + NR_SYS_REGS + (tag - TAG_VREG) as usize,
+        TAG_FPSR => 75 + NR_SYS_REGS,
+        TAG_FPCR => 76 + NR_SYS_REGS,
+        _ => return None,
+    };
+    Some(index)
+}
+
+/// Writes the records of a snapshot into a buffer large enough for them.
+struct Writer<'a> {
+    buf: &'a mut [u8],
+    pos: usize,
+}
+
+impl Writer<'_> {
+    /// Appends `data` to the buffer.
+    fn put(&mut self, data: &[u8]) {
+        self.buf[self.pos..self.pos + data.len()].copy_from_slice(data);
+        self.pos += data.len();
+    }
+
+    /// Appends the record `tag` with `value`.
+    fn record(&mut self, tag: u16, value: &[u8]) {
+        self.put(&tag.to_le_bytes());
+        self.put(&(value.len() as u16).to_le_bytes());
+        self.put(value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axerrno::AxError;
+
+    use super::*;
+
+    /// Returns a snapshot with a distinct value in every register.
+    fn sample() -> VCpuSnapshot {
+        let mut snapshot = VCpuSnapshot {
+            mpidr: 0x8000_0001,
+            aarch32: true,
+            sp_el0: 0x1000,
+            pc: 0x4008_0000,
+            pstate: 0x3c5,
+            cntkctl_el1: 1,
+            cntv_ctl_el0: 2,
+            cntv_cval_el0: 3,
+            cntp_ctl_el0: 4,
+            cntp_cval_el0: 5,
+            cntvoff_el2: 6,
+            host_counter: 7,
+            fpsr: 8,
+            fpcr: 9,
+            ..Default::default()
+        };
+        for (i, value) in snapshot.gpr.iter_mut().enumerate() {
+            *value = 0x100 + i as u64;
+        }
+        for (i, value) in snapshot.sys_regs.iter_mut().enumerate() {
+            *value = 0x200 + i as u64;
+        }
+        for (i, value) in snapshot.vregs.iter_mut().enumerate() {
+            *value = ((i as u128) << 64) | (0x400 + i as u128);
+        }
+        snapshot
+    }
+
+    fn encode(snapshot: &VCpuSnapshot) -> [u8; VCpuSnapshot::ENCODED_LEN] {
+        let mut buf = [0; VCpuSnapshot::ENCODED_LEN];
+        assert_eq!(snapshot.to_bytes(&mut buf), Ok(VCpuSnapshot::ENCODED_LEN));
+        buf
+    }
+
+    #[test]
+    fn round_trip() {
+        let snapshot = sample();
+        let buf = encode(&snapshot);
+        assert_eq!(VCpuSnapshot::from_bytes(&buf), Ok(snapshot));
+        assert_eq!(
+            snapshot.to_bytes(&mut [0; VCpuSnapshot::ENCODED_LEN - 1]),
+            Err(AxError::NoMemory)
+        );
+    }
+
+    #[test]
+    fn unknown_records_are_skipped() {
+        let snapshot = sample();
+        let mut buf = [0; VCpuSnapshot::ENCODED_LEN + 6];
+        buf[..VCpuSnapshot::ENCODED_LEN].copy_from_slice(&encode(&snapshot));
+        buf[VCpuSnapshot::ENCODED_LEN..].copy_from_slice(&[0xff, 0x7f, 2, 0, 0xaa, 0xbb]);
+        assert_eq!(VCpuSnapshot::from_bytes(&buf), Ok(snapshot));
+    }
+
+    #[test]
+    fn incomplete_snapshots_are_refused() {
+        let buf = encode(&sample());
+        // No record at all.
+        assert_eq!(
+            VCpuSnapshot::from_bytes(&buf[..HEADER_LEN]),
+            Err(AxError::InvalidData)
+        );
+        // Without the FPCR record, the last one.
+        assert_eq!(
+            VCpuSnapshot::from_bytes(&buf[..VCpuSnapshot::ENCODED_LEN - RECORD_HEADER_LEN - 8]),
+            Err(AxError::InvalidData)
+        );
+        // Cut in the middle of a record.
+        assert_eq!(
+            VCpuSnapshot::from_bytes(&buf[..VCpuSnapshot::ENCODED_LEN - 1]),
+            Err(AxError::InvalidData)
+        );
+    }
+
+    #[test]
+    fn invalid_headers_are_refused() {
+        let mut buf = encode(&sample());
+        buf[4] = 2;
+        assert_eq!(VCpuSnapshot::from_bytes(&buf), Err(AxError::InvalidData));
+        buf[0] = b'X';
+        assert_eq!(VCpuSnapshot::from_bytes(&buf), Err(AxError::InvalidData));
+    }
+}
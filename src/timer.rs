@@ -0,0 +1,129 @@
+// Copyright 2025 The Axvisor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use aarch64_cpu::registers::{CNTPCT_EL0, Readable};
+use axaddrspace::device::SysRegAddr;
+
+/// `CNTx_CTL.ENABLE`, enables the timer.
+pub const CNT_CTL_ENABLE: u64 = 1 << 0;
+/// `CNTx_CTL.IMASK`, masks the timer interrupt.
+pub const CNT_CTL_IMASK: u64 = 1 << 1;
+/// `CNTx_CTL.ISTATUS`, set when the timer condition is met, read-only.
+pub const CNT_CTL_ISTATUS: u64 = 1 << 2;
+
+/// CNTP_TVAL_EL0, op0 = 3, op1 = 3, CRn = 14, CRm = 2, op2 = 0.
+const SYSREG_CNTP_TVAL_EL0: SysRegAddr = SysRegAddr::new(0x30_F804);
+/// CNTP_CTL_EL0, op0 = 3, op1 = 3, CRn = 14, CRm = 2, op2 = 1.
+const SYSREG_CNTP_CTL_EL0: SysRegAddr = SysRegAddr::new(0x32_F804);
+/// CNTP_CVAL_EL0, op0 = 3, op1 = 3, CRn = 14, CRm = 2, op2 = 2.
+const SYSREG_CNTP_CVAL_EL0: SysRegAddr = SysRegAddr::new(0x34_F804);
+/// CNTPCT_EL0, op0 = 3, op1 = 3, CRn = 14, CRm = 0, op2 = 1.
+const SYSREG_CNTPCT_EL0: SysRegAddr = SysRegAddr::new(0x32_F800);
+/// AArch32 CNTP_TVAL, p15, opc1 = 0, c14, c2, opc2 = 0.
+const SYSREG_CP15_CNTP_TVAL: SysRegAddr = SysRegAddr::new(0x00_3804);
+/// AArch32 CNTP_CTL, p15, opc1 = 0, c14, c2, opc2 = 1.
+const SYSREG_CP15_CNTP_CTL: SysRegAddr = SysRegAddr::new(0x02_3804);
+
+/// Returns whether the timer condition of a timer with `ctl` and `cval` is met at `now`.
+pub const fn timer_condition_met(ctl: u64, cval: u64, now: u64) -> bool {
+    ctl & CNT_CTL_ENABLE != 0 && now >= cval
+}
+
+/// Returns whether the interrupt of a timer with `ctl` read from the hardware is asserted.
+pub const fn timer_irq_asserted(ctl: u64) -> bool {
+    ctl & (CNT_CTL_ENABLE | CNT_CTL_IMASK | CNT_CTL_ISTATUS) == CNT_CTL_ENABLE | CNT_CTL_ISTATUS
+}
+
+/// Returns the deadline of a timer with `ctl` and `cval` in the counter it compares against,
+/// `None` if it is disabled or its interrupt is masked.
+pub const fn timer_deadline(ctl: u64, cval: u64) -> Option<u64> {
+    if ctl & CNT_CTL_ENABLE != 0 && ctl & CNT_CTL_IMASK == 0 {
+        Some(cval)
+    } else {
+        None
+    }
+}
+
+/// The EL1 physical timer of a vCPU, emulated when timers are not passed through.
+///
+/// With `CNTHCTL_EL2.{EL1PCEN, EL1PCTEN}` cleared, guest accesses to `CNTP_CTL`, `CNTP_CVAL`,
+/// `CNTP_TVAL` and `CNTPCT` are trapped and emulated here. The AArch32 64-bit MCRR/MRRC accesses
+/// to `CNTP_CVAL` and `CNTPCT` are numbered as their AArch64 counterparts. The emulated physical counter is the
+/// host counter minus `CNTVOFF_EL2` of the vCPU, i.e., the same as the virtual counter, so that
+/// guest time does not depend on which of the two counters the guest reads.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EmulatedPhysTimer {
+    /// `CNTP_CTL` without `ISTATUS`, which is computed when read.
+    ctl: u64,
+    /// `CNTP_CVAL`, in the guest physical counter.
+    cval: u64,
+}
+
+impl EmulatedPhysTimer {
+    /// Returns `CNTP_CTL` and `CNTP_CVAL` of the timer.
+    pub fn state(&self) -> (u64, u64) {
+        (self.ctl, self.cval)
+    }
+
+    /// Sets `CNTP_CTL` and `CNTP_CVAL` of the timer, e.g. when restoring a snapshot.
+    pub fn set_state(&mut self, ctl: u64, cval: u64) {
+        self.ctl = ctl & !CNT_CTL_ISTATUS;
+        self.cval = cval;
+    }
+
+    /// Emulates a read of the system register `addr` by the guest, with `cntvoff` as the offset
+    /// of the guest counter.
+    ///
+    /// Returns `None` if `addr` is not a register of the physical timer.
+    pub fn read(&self, addr: SysRegAddr, cntvoff: u64) -> Option<u64> {
+        let now = CNTPCT_EL0.get().wrapping_sub(cntvoff);
+        match addr {
+            SYSREG_CNTP_CTL_EL0 | SYSREG_CP15_CNTP_CTL => {
+                if timer_condition_met(self.ctl, self.cval, now) {
+                    Some(self.ctl | CNT_CTL_ISTATUS)
+                } else {
+                    Some(self.ctl)
+                }
+            }
+            SYSREG_CNTP_CVAL_EL0 => Some(self.cval),
+            // TVAL is a signed 32-bit down-counter, sign-extended when read.
+            SYSREG_CNTP_TVAL_EL0 | SYSREG_CP15_CNTP_TVAL => {
+                Some(self.cval.wrapping_sub(now) as i32 as u64)
+            }
+            SYSREG_CNTPCT_EL0 => Some(now),
+            _ => None,
+        }
+    }
+
+    /// Emulates a write of `value` to the system register `addr` by the guest, with `cntvoff` as
+    /// the offset of the guest counter.
+    ///
+    /// Returns `false` if `addr` is not a register of the physical timer.
+    pub fn write(&mut self, addr: SysRegAddr, value: u64, cntvoff: u64) -> bool {
+        match addr {
+            SYSREG_CNTP_CTL_EL0 | SYSREG_CP15_CNTP_CTL => {
+                self.ctl = value & (CNT_CTL_ENABLE | CNT_CTL_IMASK);
+            }
+            SYSREG_CNTP_CVAL_EL0 => self.cval = value,
+            SYSREG_CNTP_TVAL_EL0 | SYSREG_CP15_CNTP_TVAL => {
+                let now = CNTPCT_EL0.get().wrapping_sub(cntvoff);
+                self.cval = now.wrapping_add(value as i32 as u64);
+            }
+            // CNTPCT is read-only, writes are ignored.
+            SYSREG_CNTPCT_EL0 => {}
+            _ => return false,
+        }
+        true
+    }
+}
@@ -0,0 +1,225 @@
+// Copyright 2025 The Axvisor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/// `HCR_EL2.TID1`, traps reads of `REVIDR_EL1` and `AIDR_EL1`.
+const HCR_EL2_TID1: u64 = 1 << 16;
+/// `HCR_EL2.TID2`, traps accesses to `CTR_EL0`, `CCSIDR_EL1`, `CLIDR_EL1` and `CSSELR_EL1`.
+const HCR_EL2_TID2: u64 = 1 << 17;
+/// `HCR_EL2.TID3`, traps reads of the ID registers in `op0 = 3, op1 = 0, CRn = 0, CRm = 1..=7`.
+const HCR_EL2_TID3: u64 = 1 << 18;
+/// `HCR_EL2.TACR`, traps accesses to `ACTLR_EL1`.
+const HCR_EL2_TACR: u64 = 1 << 21;
+/// `HCR_EL2.TSW`, traps data cache maintenance by set/way.
+const HCR_EL2_TSW: u64 = 1 << 22;
+/// `HCR_EL2.TVM`, traps writes to the virtual memory control registers.
+pub(crate) const HCR_EL2_TVM: u64 = 1 << 26;
+/// `HCR_EL2.TRVM`, traps reads of the virtual memory control registers.
+const HCR_EL2_TRVM: u64 = 1 << 30;
+
+/// `HSTR_EL2.T<n>`, `T4` and `T14` are RES0.
+const HSTR_EL2_T_MASK: u64 = 0xbfef;
+
+/// `CPTR_EL2.TTA`, traps accesses to the trace registers.
+const CPTR_EL2_TTA: u64 = 1 << 20;
+/// `CPTR_EL2.TAM`, traps accesses to the activity monitor registers.
+const CPTR_EL2_TAM: u64 = 1 << 30;
+/// `CPTR_EL2.TCPAC`, traps accesses to `CPACR_EL1` from EL1.
+const CPTR_EL2_TCPAC: u64 = 1 << 31;
+
+/// `MDCR_EL2.HPMN`, the number of PMU event counters accessible from EL1 and EL0.
+pub(crate) const MDCR_EL2_HPMN_MASK: u64 = 0x1f;
+/// `MDCR_EL2.TPMCR`, traps accesses to `PMCR_EL0`.
+const MDCR_EL2_TPMCR: u64 = 1 << 5;
+/// `MDCR_EL2.TPM`, traps accesses to all the PMU registers.
+const MDCR_EL2_TPM: u64 = 1 << 6;
+/// `MDCR_EL2.HPME`, enables the PMU event counters reserved for EL2.
+pub(crate) const MDCR_EL2_HPME: u64 = 1 << 7;
+/// `MDCR_EL2.TDA`, traps accesses to the debug registers other than the ones below.
+const MDCR_EL2_TDA: u64 = 1 << 9;
+/// `MDCR_EL2.TDOSA`, traps accesses to the OS-related debug registers, e.g. `OSLAR_EL1`.
+const MDCR_EL2_TDOSA: u64 = 1 << 10;
+/// `MDCR_EL2.TDRA`, traps accesses to the debug ROM registers.
+const MDCR_EL2_TDRA: u64 = 1 << 11;
+
+/// Which guest accesses to system registers are trapped to the hypervisor, on top of the ones
+/// the vCPU always traps for itself (e.g. SMC, FP/SIMD and the emulated physical timer).
+///
+/// The policy composes the trap bits of `HCR_EL2`, `HSTR_EL2`, `CPTR_EL2` and `MDCR_EL2` of the
+/// vCPU. Nothing is trapped by default, traps are added with the builder methods:
+///
+/// ```
+/// # use arm_vcpu::TrapPolicy;
+/// let policy = TrapPolicy::new().trap_id_group3().trap_set_way().trap_vm_controls();
+/// ```
+///
+/// A trapped access is reported as [`AxVCpuExitReason::SysRegRead`] or
+/// [`AxVCpuExitReason::SysRegWrite`], with the guest PC already advanced past the instruction,
+/// unless the vCPU emulates the register itself. AArch32 MCR/MRC accesses to CP15 and CP14 are
+/// reported with `op0` equal to 0 and 2 respectively, see [`SysRegAddr`]. 64-bit MCRR writes to
+/// `TTBR0` and `TTBR1` are reported as writes to `TTBR0_EL1` and `TTBR1_EL1`, which they are
+/// mapped to, and MRRC reads of them are answered by the vCPU, as are the 64-bit accesses to
+/// `CNTPCT` and `CNTP_CVAL` of the emulated physical timer; other 64-bit MCRR/MRRC accesses are
+/// not supported and are reported as [`AxVCpuExitReason::FailEntry`].
+///
+/// [`AxVCpuExitReason::SysRegRead`]: axvcpu::AxVCpuExitReason::SysRegRead
+/// [`AxVCpuExitReason::SysRegWrite`]: axvcpu::AxVCpuExitReason::SysRegWrite
+/// [`AxVCpuExitReason::FailEntry`]: axvcpu::AxVCpuExitReason::FailEntry
+/// [`SysRegAddr`]: axaddrspace::device::SysRegAddr
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TrapPolicy {
+    hcr_el2: u64,
+    hstr_el2: u64,
+    cptr_el2: u64,
+    mdcr_el2: u64,
+}
+
+impl TrapPolicy {
+    /// Creates a policy that traps nothing.
+    pub const fn new() -> Self {
+        Self {
+            hcr_el2: 0,
+            hstr_el2: 0,
+            cptr_el2: 0,
+            mdcr_el2: 0,
+        }
+    }
+
+    /// Traps reads of `REVIDR_EL1` and `AIDR_EL1` (`HCR_EL2.TID1`).
+    pub const fn trap_id_group1(mut self) -> Self {
+        self.hcr_el2 |= HCR_EL2_TID1;
+        self
+    }
+
+    /// Traps accesses to the cache ID registers, `CTR_EL0`, `CCSIDR_EL1`, `CLIDR_EL1` and
+    /// `CSSELR_EL1` (`HCR_EL2.TID2`).
+    ///
+    /// As writes to `CSSELR_EL1` are trapped too, the VMM has to emulate it.
+    pub const fn trap_id_group2(mut self) -> Self {
+        self.hcr_el2 |= HCR_EL2_TID2;
+        self
+    }
+
+    /// Traps reads of the feature ID registers, `ID_AA64*_EL1` and their AArch32 counterparts
+    /// (`HCR_EL2.TID3`).
+    pub const fn trap_id_group3(mut self) -> Self {
+        self.hcr_el2 |= HCR_EL2_TID3;
+        self
+    }
+
+    /// Traps accesses to `ACTLR_EL1` (`HCR_EL2.TACR`).
+    pub const fn trap_auxiliary_control(mut self) -> Self {
+        self.hcr_el2 |= HCR_EL2_TACR;
+        self
+    }
+
+    /// Traps data cache maintenance by set/way, `DC ISW`, `DC CSW` and `DC CISW`
+    /// (`HCR_EL2.TSW`).
+    ///
+    /// Set/way operations are emulated by the vCPU, which cleans and invalidates the whole guest
+    /// memory by VA instead, so they are not reported to the VMM. This is recommended for guests
+    /// that use them during boot, such as Linux and many RTOSes.
+    pub const fn trap_set_way(mut self) -> Self {
+        self.hcr_el2 |= HCR_EL2_TSW;
+        self
+    }
+
+    /// Traps reads and writes of the virtual memory control registers, e.g. `SCTLR_EL1`,
+    /// `TTBR0_EL1` and `TCR_EL1` (`HCR_EL2.TRVM` and `HCR_EL2.TVM`).
+    ///
+    /// The vCPU applies trapped accesses to the saved guest state before reporting them, so
+    /// the VMM only has to observe them, e.g. to find out when the guest enables its MMU.
+    pub const fn trap_vm_controls(mut self) -> Self {
+        self.hcr_el2 |= HCR_EL2_TVM | HCR_EL2_TRVM;
+        self
+    }
+
+    /// Traps writes of the virtual memory control registers only (`HCR_EL2.TVM`), see
+    /// [`Self::trap_vm_controls`].
+    pub const fn trap_vm_control_writes(mut self) -> Self {
+        self.hcr_el2 |= HCR_EL2_TVM;
+        self
+    }
+
+    /// Traps AArch32 accesses to the CP15 registers with the primary register `CRn` (or `CRm`
+    /// for 64-bit accesses) equal to `n`, for every bit `n` set in `mask` (`HSTR_EL2.T<n>`).
+    ///
+    /// Bits 4 and 14 are ignored, as `CRn` 4 is not used and the generic timer registers in
+    /// `CRn` 14 are trapped with `CNTHCTL_EL2`.
+    pub const fn trap_cp15(mut self, mask: u16) -> Self {
+        self.hstr_el2 |= mask as u64 & HSTR_EL2_T_MASK;
+        self
+    }
+
+    /// Traps accesses to the trace registers (`CPTR_EL2.TTA`).
+    pub const fn trap_trace(mut self) -> Self {
+        self.cptr_el2 |= CPTR_EL2_TTA;
+        self
+    }
+
+    /// Traps accesses to the activity monitor registers (`CPTR_EL2.TAM`).
+    pub const fn trap_activity_monitors(mut self) -> Self {
+        self.cptr_el2 |= CPTR_EL2_TAM;
+        self
+    }
+
+    /// Traps accesses to `CPACR_EL1` from EL1 (`CPTR_EL2.TCPAC`).
+    pub const fn trap_cpacr(mut self) -> Self {
+        self.cptr_el2 |= CPTR_EL2_TCPAC;
+        self
+    }
+
+    /// Traps accesses to the debug registers, including the OS lock and debug ROM registers
+    /// (`MDCR_EL2.TDA`, `MDCR_EL2.TDOSA` and `MDCR_EL2.TDRA`).
+    pub const fn trap_debug(mut self) -> Self {
+        self.mdcr_el2 |= MDCR_EL2_TDA | MDCR_EL2_TDOSA | MDCR_EL2_TDRA;
+        self
+    }
+
+    /// Traps accesses to the performance monitor registers (`MDCR_EL2.TPM` and
+    /// `MDCR_EL2.TPMCR`).
+    pub const fn trap_pmu(mut self) -> Self {
+        self.mdcr_el2 |= MDCR_EL2_TPM | MDCR_EL2_TPMCR;
+        self
+    }
+
+    /// Returns the trap bits of `HCR_EL2` of the policy.
+    pub const fn hcr_el2(&self) -> u64 {
+        self.hcr_el2
+    }
+
+    /// Returns `HSTR_EL2` of the policy.
+    pub const fn hstr_el2(&self) -> u64 {
+        self.hstr_el2
+    }
+
+    /// Returns the trap bits of `CPTR_EL2` of the policy.
+    pub const fn cptr_el2(&self) -> u64 {
+        self.cptr_el2
+    }
+
+    /// Returns the trap bits of `MDCR_EL2` of the policy.
+    pub const fn mdcr_el2(&self) -> u64 {
+        self.mdcr_el2
+    }
+
+    /// Returns whether reads of the virtual memory control registers are trapped.
+    pub const fn traps_vm_control_reads(&self) -> bool {
+        self.hcr_el2 & HCR_EL2_TRVM != 0
+    }
+
+    /// Returns whether writes of the virtual memory control registers are trapped.
+    pub const fn traps_vm_control_writes(&self) -> bool {
+        self.hcr_el2 & HCR_EL2_TVM != 0
+    }
+}
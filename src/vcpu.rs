@@ -14,15 +14,33 @@
 
 use core::marker::PhantomData;
 
+use aarch64_cpu::asm::barrier;
 use aarch64_cpu::registers::*;
-use axaddrspace::{GuestPhysAddr, HostPhysAddr, device::SysRegAddr};
-use axerrno::AxResult;
+use axaddrspace::{
+    GuestPhysAddr, HostPhysAddr,
+    device::{AccessWidth, SysRegAddr},
+};
+use axerrno::{AxResult, ax_err};
 use axvcpu::{AxArchVCpu, AxVCpuExitReason, AxVCpuHal};
 
 use crate::TrapFrame;
-use crate::context_frame::GuestSystemRegisters;
-use crate::exception::{TrapKind, handle_exception_sync};
-use crate::exception_utils::exception_class_value;
+use crate::cache::{SetWayTracker, flush_guest_memory, is_dc_set_way};
+use crate::context_frame::{
+    FpSimdRegisters, GicV3Registers, GuestSystemRegisters, HCR_EL2_VSE, SPSR_AARCH32_MODE_ABT,
+    SPSR_AARCH32_MODE_SVC, SPSR_AARCH32_MODE_UND, SPSR_AARCH32_STATE, SPSR_AARCH32_T, SVE_VL_MAX,
+    SveRegisters,
+};
+use crate::decode::LoadStore;
+use crate::exception::{TrapKind, fail_entry, handle_exception_sync, handle_serror};
+use crate::exception_utils::{aarch32_it_advance, exception_class_value};
+use crate::exit::{Aarch64ExitDetail, Endianness};
+use crate::id_regs::IdRegisters;
+use crate::regs::VCpuReg;
+use crate::sgi::decode_sgi_register;
+use crate::smc::SmcPolicy;
+use crate::snapshot::VCpuSnapshot;
+use crate::timer::{EmulatedPhysTimer, timer_condition_met, timer_deadline, timer_irq_asserted};
+use crate::trap::{HCR_EL2_TVM, MDCR_EL2_HPME, MDCR_EL2_HPMN_MASK, TrapPolicy};
 
 #[percpu::def_percpu]
 static HOST_SP_EL0: u64 = 0;
@@ -37,6 +55,52 @@ unsafe fn restore_host_sp_el0() {
     SP_EL0.set(unsafe { HOST_SP_EL0.read_current_raw() });
 }
 
+/// Host's FP/SIMD registers, saved when a guest takes over the FP/SIMD unit of this CPU.
+#[percpu::def_percpu]
+static HOST_FP_REGS: FpSimdRegisters = FpSimdRegisters::new();
+
+/// Host's `ZCR_EL2`, saved when a guest with SVE takes over the FP/SIMD unit of this CPU.
+#[percpu::def_percpu]
+static HOST_ZCR_EL2: u64 = 0;
+
+/// `HCR_EL2.TWI`, traps WFI from EL0 and EL1 to EL2.
+const HCR_EL2_TWI: u64 = 1 << 13;
+/// `HCR_EL2.TWE`, traps WFE from EL0 and EL1 to EL2.
+const HCR_EL2_TWE: u64 = 1 << 14;
+
+/// `ESR_ELx.EC` shift.
+const ESR_ELX_EC_SHIFT: u64 = 26;
+/// `ESR_ELx.IL`, set for 32-bit instructions.
+const ESR_ELX_IL: u64 = 1 << 25;
+/// Exception classes of instruction aborts from a lower EL and from the current EL.
+const ESR_EC_IABT_LOW: u64 = 0b10_0000;
+const ESR_EC_IABT_CUR: u64 = 0b10_0001;
+/// Exception classes of data aborts from a lower EL and from the current EL.
+const ESR_EC_DABT_LOW: u64 = 0b10_0100;
+const ESR_EC_DABT_CUR: u64 = 0b10_0101;
+
+/// `SPSR.M[3:0]` of AArch64 states, where `SPSR.M[3:2]` is the exception level.
+const SPSR_EL_MASK: u64 = 0b1100;
+
+/// Offsets of the undefined instruction, prefetch abort and data abort vectors of AArch32.
+const AARCH32_VECTOR_UND: u64 = 0x4;
+const AARCH32_VECTOR_PABT: u64 = 0xc;
+const AARCH32_VECTOR_DABT: u64 = 0x10;
+
+/// `CPTR_EL2.TFP`, traps FP/SIMD accesses from EL0, EL1 and EL2 to EL2.
+const CPTR_EL2_TFP: u64 = 1 << 10;
+/// `CPTR_EL2.TSM`, traps SME accesses from EL0, EL1 and EL2 to EL2.
+const CPTR_EL2_TSM: u64 = 1 << 12;
+/// `CPTR_EL2.TZ`, traps SVE accesses from EL0, EL1 and EL2 to EL2.
+const CPTR_EL2_TZ: u64 = 1 << 8;
+/// `ID_AA64PFR0_EL1.SVE`, bits \[35:32\].
+const ID_AA64PFR0_SVE_SHIFT: u32 = 32;
+/// `ID_AA64PFR1_EL1.SME`, bits \[27:24\].
+const ID_AA64PFR1_SME_SHIFT: u32 = 24;
+
+/// RES1 bits of `CPTR_EL2` when `HCR_EL2.E2H` is 0.
+const CPTR_EL2_RES1: u64 = 0x22ff;
+
 /// (v)CPU register state that must be saved or restored when entering/exiting a VM or switching
 /// between VMs.
 #[repr(C)]
@@ -46,19 +110,124 @@ pub struct VmCpuRegisters {
     pub trap_context_regs: TrapFrame,
     /// virtual machine system regs setting
     pub vm_system_regs: GuestSystemRegisters,
+    /// guest FP/SIMD registers
+    pub fp_simd_regs: FpSimdRegisters,
+    /// guest GICv3 virtual CPU interface registers
+    pub gic_regs: GicV3Registers,
+}
+
+/// An MMIO read reported to the VMM, to be completed with [`Aarch64VCpu::complete_mmio_read`].
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct PendingMmioRead {
+    /// The destination register.
+    pub reg: usize,
+    /// The width of the access.
+    pub width: AccessWidth,
+    /// The width of the destination register, `Xn` or `Wn`.
+    pub reg_width: AccessWidth,
+    /// Whether the value is sign-extended to `reg_width` (`ISS.SSE`).
+    pub sign_ext: bool,
+    /// The byte offset of the access in the destination SIMD&FP register, `None` if the
+    /// destination is a general-purpose register.
+    pub fp_offset: Option<usize>,
+    /// Whether the access is one half of a 128-bit SIMD&FP register, the other half being
+    /// loaded by another access.
+    pub fp_half: bool,
+}
+
+/// A load/store on MMIO without a valid instruction syndrome, emulated from its decoded
+/// instruction as a sequence of MMIO exits, one for each register, or for each half of a 128-bit
+/// SIMD&FP register.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct MmioEmulation {
+    /// The decoded instruction.
+    pub access: LoadStore,
+    /// The IPA of each access.
+    pub addrs: [GuestPhysAddr; 4],
+    /// The index of the next access to report.
+    pub next: usize,
+    /// The value written back to the base register once all the accesses are done.
+    pub writeback: Option<u64>,
+}
+
+impl MmioEmulation {
+    /// Returns the width of each access in bytes, 128-bit registers being accessed in two halves.
+    pub fn width(&self) -> usize {
+        self.access.size.min(8)
+    }
+
+    /// Returns the number of accesses.
+    pub fn count(&self) -> usize {
+        let regs = if self.access.rt2.is_some() { 2 } else { 1 };
+        regs * self.access.size.div_ceil(8)
+    }
+
+    /// Returns the register and the byte offset in the register of access `i`.
+    fn element(&self, i: usize) -> (usize, usize) {
+        let per_reg = self.access.size.div_ceil(8);
+        let reg = match self.access.rt2 {
+            Some(rt2) if i >= per_reg => rt2,
+            _ => self.access.rt,
+        };
+        (reg, (i % per_reg) * 8)
+    }
 }
 
 /// A virtual CPU within a guest
+///
+/// The FP/SIMD (and SVE) registers of the guest are switched lazily within a `run`: they are
+/// only loaded on the first FP/SIMD access of the guest, but they are always saved and the host
+/// ones restored before `run` returns. They are not kept on the CPU across runs, as the host or
+/// another vCPU may use the FP/SIMD unit in between, so a guest using FP/SIMD still pays for a
+/// full switch on every VM exit reported to the VMM, e.g. for each MMIO access.
 #[repr(C)]
 #[derive(Debug)]
 pub struct Aarch64VCpu<H: AxVCpuHal> {
     // DO NOT modify `guest_regs` and `host_stack_top` and their order unless you do know what you are doing!
     // DO NOT add anything before or between them unless you do know what you are doing!
-    ctx: TrapFrame,
+    pub(crate) ctx: TrapFrame,
     host_stack_top: u64,
     guest_system_regs: GuestSystemRegisters,
     /// The MPIDR_EL1 value for the vCPU.
     mpidr: u64,
+    /// Whether EL1 of the guest runs in AArch32 state.
+    aarch32: bool,
+    /// Guest FP/SIMD registers, valid when `fp_loaded` is false.
+    guest_fp_regs: FpSimdRegisters,
+    /// Guest SVE registers, valid when `fp_loaded` is false and `sve_vl` is not 0.
+    guest_sve_regs: SveRegisters,
+    /// The SVE vector length of the guest in bytes, 0 if SVE is disabled for the guest.
+    pub(crate) sve_vl: usize,
+    /// Whether the guest FP/SIMD (and SVE) registers are currently loaded on the CPU.
+    ///
+    /// FP/SIMD registers are switched lazily, see [`Self::load_guest_fp_regs`].
+    fp_loaded: bool,
+    /// The GICv3 virtual CPU interface state of the guest, if it is switched with the vCPU.
+    guest_gic_regs: Option<GicV3Registers>,
+    /// Whether the EL1 physical timer and counter are passed through to the guest.
+    passthrough_timer: bool,
+    /// The EL1 physical timer, emulated if it is not passed through.
+    ptimer: EmulatedPhysTimer,
+    /// The policy for guest SMC calls.
+    pub(crate) smc_policy: SmcPolicy,
+    /// The system register trap policy of the guest.
+    trap_policy: TrapPolicy,
+    /// The feature ID registers the guest sees, always trapped.
+    id_regs: IdRegisters,
+    /// Whether the guest used set/way cache maintenance since it last enabled its caches, in
+    /// which case writes to the VM control registers are trapped to flush the guest memory when
+    /// the guest turns its caches on or off.
+    set_way: SetWayTracker,
+    /// Details of the last VM exit, see [`Aarch64ExitDetail`].
+    pub(crate) exit_detail: Aarch64ExitDetail,
+    /// The size of the trapped instruction the guest PC has to be advanced past when the guest
+    /// is resumed, 0 if none.
+    pub(crate) pending_pc_step: usize,
+    /// The MMIO read reported by the last VM exit, if it has not been completed yet.
+    pub(crate) pending_mmio_read: Option<PendingMmioRead>,
+    /// The MMIO load/store being emulated from its instruction, if it has accesses left or has
+    /// not been completed yet.
+    pub(crate) mmio_emulation: Option<MmioEmulation>,
     _phantom: PhantomData<H>,
 }
 
@@ -72,6 +241,11 @@ pub struct Aarch64VCpuCreateConfig {
     pub mpidr_el1: u64,
     /// The address of the device tree blob.
     pub dtb_addr: usize,
+    /// Should the guest EL1 run in AArch32 state?
+    ///
+    /// If so, the guest boots in Supervisor mode, in T32 state if the entry address is odd,
+    /// with `r2` holding the address of the device tree blob, as the Linux ARM boot protocol requires.
+    pub aarch32: bool,
 }
 
 /// Configuration for setting up a new `Aarch64VCpu`
@@ -81,6 +255,43 @@ pub struct Aarch64VCpuSetupConfig {
     pub passthrough_interrupt: bool,
     /// Should the hypervisor passthrough timers to the guest?
     pub passthrough_timer: bool,
+    /// The maximum SVE vector length of the guest in bytes, 0 to disable SVE for the guest.
+    ///
+    /// It must be a multiple of 16. The vector length the guest actually gets is the largest one
+    /// supported by the CPU that does not exceed this value.
+    pub sve_max_vector_length: usize,
+    /// Should WFI executed by the guest be trapped?
+    ///
+    /// If so, a WFI is reported as [`AxVCpuExitReason::Halt`], so that the vCPU can be blocked
+    /// until an interrupt is pending for it, instead of holding the physical CPU. The deadline of
+    /// the guest timers is recorded as [`Aarch64ExitDetail::Halt`].
+    pub trap_wfi: bool,
+    /// Should WFE executed by the guest be trapped?
+    ///
+    /// If so, a WFE is reported as [`AxVCpuExitReason::Nothing`], giving the hypervisor a chance to
+    /// yield the physical CPU, e.g. to the vCPU holding the spinlock the guest is waiting for.
+    pub trap_wfe: bool,
+    /// The policy for guest SMC calls that are not emulated by the vCPU.
+    ///
+    /// All of them are denied by default.
+    pub smc_policy: SmcPolicy,
+    /// Should the GICv3 virtual CPU interface (`ICH_*_EL2`) be switched with the vCPU?
+    ///
+    /// If so, the list registers and the virtual CPU interface state are saved on every VM exit
+    /// and restored on every VM entry, so that several vCPUs can share a physical CPU, and
+    /// `inject_interrupt` writes to the saved list registers of the vCPU. Otherwise they are left
+    /// to the virtual GIC implementation.
+    pub gicv3_context: bool,
+    /// Which guest accesses to system registers are trapped and reported to the VMM.
+    ///
+    /// Nothing is trapped by default.
+    pub trap_policy: TrapPolicy,
+    /// The feature ID registers the guest sees, `None` for the host ones.
+    ///
+    /// ID register reads are always trapped (`HCR_EL2.TID3`) and answered by the vCPU from this
+    /// view, with the features the vCPU does not provide, e.g. SVE if it is disabled and SME,
+    /// hidden.
+    pub id_registers: Option<IdRegisters>,
 }
 
 impl<H: AxVCpuHal> axvcpu::AxArchVCpu for Aarch64VCpu<H> {
@@ -90,20 +301,44 @@ impl<H: AxVCpuHal> axvcpu::AxArchVCpu for Aarch64VCpu<H> {
 
     fn new(_vm_id: usize, _vcpu_id: usize, config: Self::CreateConfig) -> AxResult<Self> {
         let mut ctx = TrapFrame::default();
-        ctx.set_argument(config.dtb_addr);
+        if config.aarch32 {
+            if !el1_aarch32_supported() {
+                return ax_err!(Unsupported, "AArch32 is not supported at EL1");
+            }
+            // r0 = 0, r1 = ~0 (no machine type, use the device tree), r2 = dtb.
+            ctx.set_gpr(1, u32::MAX as usize);
+            ctx.set_gpr(2, config.dtb_addr);
+        } else {
+            ctx.set_argument(config.dtb_addr);
+        }
 
         Ok(Self {
             ctx,
             host_stack_top: 0,
             guest_system_regs: GuestSystemRegisters::default(),
             mpidr: config.mpidr_el1,
+            aarch32: config.aarch32,
+            guest_fp_regs: FpSimdRegisters::default(),
+            guest_sve_regs: SveRegisters::default(),
+            sve_vl: 0,
+            fp_loaded: false,
+            guest_gic_regs: None,
+            passthrough_timer: false,
+            ptimer: EmulatedPhysTimer::default(),
+            smc_policy: SmcPolicy::default(),
+            trap_policy: TrapPolicy::new(),
+            id_regs: IdRegisters::new(),
+            set_way: SetWayTracker::default(),
+            exit_detail: Aarch64ExitDetail::None,
+            pending_pc_step: 0,
+            pending_mmio_read: None,
+            mmio_emulation: None,
             _phantom: PhantomData,
         })
     }
 
     fn setup(&mut self, config: Self::SetupConfig) -> AxResult {
-        self.init_hv(config);
-        Ok(())
+        self.init_hv(config)
     }
 
     fn set_entry(&mut self, entry: GuestPhysAddr) -> AxResult {
@@ -119,17 +354,52 @@ impl<H: AxVCpuHal> axvcpu::AxArchVCpu for Aarch64VCpu<H> {
     }
 
     fn run(&mut self) -> AxResult<AxVCpuExitReason> {
-        // Run guest.
-        let exit_reson = unsafe {
-            // Save host SP_EL0 to the ctx becase it's used as current task ptr.
-            // This has to be done before vm system regs are restored.
-            save_host_sp_el0();
-            self.restore_vm_system_regs();
-            self.run_guest()
+        // Report the remaining accesses of an emulated MMIO load/store without entering the guest.
+        if let Some(exit_reason) = self.step_mmio_emulation() {
+            return Ok(exit_reason);
+        }
+
+        let host_cptr_el2 = CPTR_EL2.get();
+        // The EL1 physical timer is shared with the host if it is passed through to the guest.
+        let host_cntp = self
+            .passthrough_timer
+            .then(|| (CNTP_CTL_EL0.get(), CNTP_CVAL_EL0.get()));
+
+        let result = loop {
+            self.commit_pc_advance();
+
+            // Run guest.
+            let exit_reson = unsafe {
+                // Save host SP_EL0 to the ctx becase it's used as current task ptr.
+                // This has to be done before vm system regs are restored.
+                save_host_sp_el0();
+                self.restore_vm_system_regs();
+                self.run_guest()
+            };
+
+            let fp_loaded = self.fp_loaded;
+            let result = self.vmexit_handler(exit_reson);
+            // Leave the guest PC at the trapped instruction if its emulation failed.
+            if result.is_err() {
+                self.pending_pc_step = 0;
+                self.mmio_emulation = None;
+            }
+
+            // The guest trapped on its first FP/SIMD access and got its FP/SIMD registers
+            // loaded, re-enter it directly as there is nothing for the VMM to do.
+            if fp_loaded || !self.fp_loaded || !matches!(result, Ok(AxVCpuExitReason::Nothing)) {
+                break result;
+            }
         };
 
-        let trap_kind = TrapKind::try_from(exit_reson as u8).expect("Invalid TrapKind");
-        self.vmexit_handler(trap_kind)
+        unsafe { self.put_guest_fp_regs() };
+        CPTR_EL2.set(host_cptr_el2);
+        if let Some((ctl, cval)) = host_cntp {
+            CNTP_CVAL_EL0.set(cval);
+            CNTP_CTL_EL0.set(ctl);
+        }
+
+        result
     }
 
     fn bind(&mut self) -> AxResult {
@@ -145,6 +415,9 @@ impl<H: AxVCpuHal> axvcpu::AxArchVCpu for Aarch64VCpu<H> {
     }
 
     fn inject_interrupt(&mut self, vector: usize) -> AxResult {
+        if let Some(gic_regs) = &mut self.guest_gic_regs {
+            return gic_regs.inject(vector as u32);
+        }
         axvisor_api::arch::hardware_inject_virtual_interrupt(vector as u8);
         Ok(())
     }
@@ -157,28 +430,47 @@ impl<H: AxVCpuHal> axvcpu::AxArchVCpu for Aarch64VCpu<H> {
 
 // Private function
 impl<H: AxVCpuHal> Aarch64VCpu<H> {
-    fn init_hv(&mut self, config: Aarch64VCpuSetupConfig) {
-        self.ctx.spsr = (SPSR_EL1::M::EL1h
-            + SPSR_EL1::I::Masked
-            + SPSR_EL1::F::Masked
-            + SPSR_EL1::A::Masked
-            + SPSR_EL1::D::Masked)
-            .value;
-        self.init_vm_context(config);
+    fn init_hv(&mut self, config: Aarch64VCpuSetupConfig) -> AxResult {
+        self.ctx.spsr = if self.aarch32 {
+            // Keep the T32 state possibly set by `set_entry`.
+            SPSR_AARCH32_MODE_SVC
+                | (SPSR_EL1::I::Masked + SPSR_EL1::F::Masked + SPSR_EL1::A::Masked).value
+                | (self.ctx.spsr & SPSR_AARCH32_T)
+        } else {
+            (SPSR_EL1::M::EL1h
+                + SPSR_EL1::I::Masked
+                + SPSR_EL1::F::Masked
+                + SPSR_EL1::A::Masked
+                + SPSR_EL1::D::Masked)
+                .value
+        };
+        self.init_vm_context(config)
     }
 
     /// Init guest context. Also set some el2 register value.
-    fn init_vm_context(&mut self, config: Aarch64VCpuSetupConfig) {
+    fn init_vm_context(&mut self, config: Aarch64VCpuSetupConfig) -> AxResult {
         // CNTHCTL_EL2.modify(CNTHCTL_EL2::EL1PCEN::SET + CNTHCTL_EL2::EL1PCTEN::SET);
         self.guest_system_regs.cntvoff_el2 = 0;
         self.guest_system_regs.cntkctl_el1 = 0;
+        self.passthrough_timer = config.passthrough_timer;
+        self.ptimer = EmulatedPhysTimer::default();
         self.guest_system_regs.cnthctl_el2 = if config.passthrough_timer {
             (CNTHCTL_EL2::EL1PCEN::SET + CNTHCTL_EL2::EL1PCTEN::SET).into()
         } else {
             (CNTHCTL_EL2::EL1PCEN::CLEAR + CNTHCTL_EL2::EL1PCTEN::CLEAR).into()
         };
 
-        self.guest_system_regs.sctlr_el1 = 0x30C50830;
+        // Trap FP/SIMD (lazily switched, see `restore_vm_system_regs`), SVE and SME.
+        self.guest_system_regs.cptr_el2 =
+            CPTR_EL2_RES1 | CPTR_EL2_TZ | CPTR_EL2_TSM | config.trap_policy.cptr_el2();
+        self.guest_system_regs.hstr_el2 = config.trap_policy.hstr_el2();
+        // The PMU event counters reserved for EL2 are left as the host configured them.
+        let host_mdcr_el2: u64;
+        unsafe { core::arch::asm!("mrs {0}, MDCR_EL2", out(reg) host_mdcr_el2) };
+        self.guest_system_regs.mdcr_el2 =
+            (host_mdcr_el2 & (MDCR_EL2_HPMN_MASK | MDCR_EL2_HPME)) | config.trap_policy.mdcr_el2();
+
+        self.guest_system_regs.sctlr_el1 = if self.aarch32 { 0x00C50078 } else { 0x30C50830 };
         self.guest_system_regs.pmcr_el0 = 0;
 
         self.guest_system_regs.vtcr_el2 = probe_vtcr_support()
@@ -188,8 +480,15 @@ impl<H: AxVCpuHal> Aarch64VCpu<H> {
                 + VTCR_EL2::IRGN0::NormalWBRAWA)
                 .value;
 
-        let mut hcr_el2 =
-            HCR_EL2::VM::Enable + HCR_EL2::TSC::EnableTrapEl1SmcToEl2 + HCR_EL2::RW::EL1IsAarch64;
+        // Route physical SErrors to EL2, which also enables virtual SErrors.
+        let mut hcr_el2 = HCR_EL2::VM::Enable
+            + HCR_EL2::TSC::EnableTrapEl1SmcToEl2
+            + HCR_EL2::AMO::SET
+            + if self.aarch32 {
+                HCR_EL2::RW::AllLowerELsAreAarch32
+            } else {
+                HCR_EL2::RW::EL1IsAarch64
+            };
 
         if !config.passthrough_interrupt {
             // Set HCR_EL2.IMO will trap IRQs to EL2 while enabling virtual IRQs.
@@ -200,7 +499,13 @@ impl<H: AxVCpuHal> Aarch64VCpu<H> {
             hcr_el2 += HCR_EL2::IMO::EnableVirtualIRQ + HCR_EL2::FMO::EnableVirtualFIQ;
         }
 
-        self.guest_system_regs.hcr_el2 = hcr_el2.into();
+        self.guest_system_regs.hcr_el2 = hcr_el2.value | config.trap_policy.hcr_el2();
+        if config.trap_wfi {
+            self.guest_system_regs.hcr_el2 |= HCR_EL2_TWI;
+        }
+        if config.trap_wfe {
+            self.guest_system_regs.hcr_el2 |= HCR_EL2_TWE;
+        }
 
         // Set VMPIDR_EL2, which provides the value of the Virtualization Multiprocessor ID.
         // This is the value returned by Non-secure EL1 reads of MPIDR.
@@ -208,17 +513,835 @@ impl<H: AxVCpuHal> Aarch64VCpu<H> {
         // Note: mind CPU cluster here.
         vmpidr |= self.mpidr;
         self.guest_system_regs.vmpidr_el2 = vmpidr;
+
+        self.smc_policy = config.smc_policy;
+        self.trap_policy = config.trap_policy;
+        self.set_way = SetWayTracker::default();
+        self.guest_gic_regs = config.gicv3_context.then(GicV3Registers::new);
+
+        self.sve_vl = match config.sve_max_vector_length {
+            0 => 0,
+            vl if vl % 16 != 0 || vl > SVE_VL_MAX => {
+                return ax_err!(InvalidInput, "invalid SVE vector length");
+            }
+            _ if !sve_supported() => return ax_err!(Unsupported, "SVE is not supported"),
+            vl => sve_effective_vl(vl),
+        };
+
+        // SVE and SME accesses are trapped by `CPTR_EL2`, so the guest must not see them even if
+        // the VMM does not provide an ID register view.
+        let mut id_regs = config.id_registers.unwrap_or_else(IdRegisters::from_host);
+        if self.sve_vl == 0 {
+            id_regs.limit_feature(IdRegisters::ID_AA64PFR0_EL1, ID_AA64PFR0_SVE_SHIFT, 0);
+            id_regs.set(IdRegisters::ID_AA64ZFR0_EL1, 0);
+        }
+        // SME is always trapped, see `CPTR_EL2_TSM`.
+        id_regs.limit_feature(IdRegisters::ID_AA64PFR1_EL1, ID_AA64PFR1_SME_SHIFT, 0);
+        id_regs.set(IdRegisters::ID_AA64SMFR0_EL1, 0);
+        self.id_regs = id_regs;
+        self.trap_policy = self.trap_policy.trap_id_group3();
+        self.guest_system_regs.hcr_el2 |= self.trap_policy.hcr_el2();
+
+        Ok(())
     }
 
     /// Set exception return pc
+    ///
+    /// For AArch32 guests, bit 0 of `elr` selects the T32 state, as `bx` does. A pending advance
+    /// of the guest PC past a trapped instruction is cancelled.
     fn set_elr(&mut self, elr: usize) {
-        self.ctx.set_exception_pc(elr);
+        self.pending_pc_step = 0;
+        self.mmio_emulation = None;
+        if self.aarch32 {
+            if elr & 1 != 0 {
+                self.ctx.spsr |= SPSR_AARCH32_T;
+            } else {
+                self.ctx.spsr &= !SPSR_AARCH32_T;
+            }
+            self.ctx.set_exception_pc(elr & !1);
+        } else {
+            self.ctx.set_exception_pc(elr);
+        }
+    }
+
+    /// Returns the general-purpose register `idx` of the guest, 31 being `XZR`.
+    pub fn get_gpr(&self, idx: usize) -> usize {
+        self.ctx.gpr(idx)
     }
 
-    /// Get general purpose register
-    #[allow(unused)]
-    fn get_gpr(&self, idx: usize) {
-        self.ctx.gpr(idx);
+    /// Returns the value of the guest register `reg`, `None` for a general-purpose register
+    /// index beyond 30.
+    ///
+    /// A pending advance of the guest PC past a trapped instruction is applied to the PC and
+    /// PSTATE, so that setting them back resumes the guest after the instruction.
+    pub fn reg(&self, reg: VCpuReg) -> Option<u64> {
+        match reg {
+            VCpuReg::X(n) if n <= 30 => Some(self.ctx.gpr[n]),
+            VCpuReg::X(_) => None,
+            VCpuReg::Pc => Some(self.resume_pc_pstate().0),
+            VCpuReg::Pstate => Some(self.resume_pc_pstate().1),
+            // `SP_EL0` is switched through the trap frame.
+            VCpuReg::SpEl0 => Some(self.ctx.sp_el0),
+            VCpuReg::Fpsr => Some(self.guest_fp_regs.fpsr),
+            VCpuReg::Fpcr => Some(self.guest_fp_regs.fpcr),
+            _ => self.guest_system_regs.reg(reg),
+        }
+    }
+
+    /// Sets the guest register `reg` to `value`, taking effect when the guest is resumed.
+    ///
+    /// Setting the PC or PSTATE cancels the pending advance of the guest PC past a trapped
+    /// instruction, as [`Self::retry_instruction`] does. No consistency check is made, e.g.
+    /// PSTATE must match the execution state of EL1 of the guest.
+    ///
+    /// Returns [`AxError::InvalidInput`](axerrno::AxError::InvalidInput) for a general-purpose
+    /// register index beyond 30.
+    pub fn set_reg(&mut self, reg: VCpuReg, value: u64) -> AxResult {
+        match reg {
+            VCpuReg::X(n) if n <= 30 => self.ctx.gpr[n] = value,
+            VCpuReg::Pc => {
+                self.retry_instruction();
+                self.ctx.elr = value;
+            }
+            VCpuReg::Pstate => {
+                self.retry_instruction();
+                self.ctx.spsr = value;
+            }
+            VCpuReg::SpEl0 => self.ctx.sp_el0 = value,
+            VCpuReg::Fpsr => self.guest_fp_regs.fpsr = value,
+            VCpuReg::Fpcr => self.guest_fp_regs.fpcr = value,
+            _ => {
+                if !self.guest_system_regs.set_reg(reg, value) {
+                    return ax_err!(InvalidInput, "invalid vCPU register");
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the SIMD&FP registers of the guest.
+    pub fn fp_regs(&self) -> &FpSimdRegisters {
+        &self.guest_fp_regs
+    }
+
+    /// Returns the SIMD&FP registers of the guest for modification, taking effect when the
+    /// guest is resumed.
+    pub fn fp_regs_mut(&mut self) -> &mut FpSimdRegisters {
+        &mut self.guest_fp_regs
+    }
+
+    /// Returns a copy of all the saved registers of the guest.
+    ///
+    /// `gic_regs` is the default state if the GICv3 virtual CPU interface is not switched with
+    /// the vCPU. A pending advance of the guest PC past a trapped instruction is applied, as
+    /// [`Self::reg`] does.
+    pub fn regs(&self) -> VmCpuRegisters {
+        let mut ctx = self.ctx;
+        (ctx.elr, ctx.spsr) = self.resume_pc_pstate();
+        VmCpuRegisters {
+            trap_context_regs: ctx,
+            vm_system_regs: self.guest_system_regs,
+            fp_simd_regs: self.guest_fp_regs,
+            gic_regs: self.guest_gic_regs.unwrap_or_default(),
+        }
+    }
+
+    /// Replaces all the saved registers of the guest with `regs`, e.g. ones returned by
+    /// [`Self::regs`], taking effect when the guest is resumed.
+    ///
+    /// This includes the EL2 controls of the vCPU, such as `HCR_EL2` and `VTTBR_EL2`. `gic_regs`
+    /// is ignored if the GICv3 virtual CPU interface is not switched with the vCPU. The pending
+    /// advance of the guest PC past a trapped instruction is cancelled.
+    pub fn set_regs(&mut self, regs: &VmCpuRegisters) {
+        self.retry_instruction();
+        self.ctx = regs.trap_context_regs;
+        self.guest_system_regs = regs.vm_system_regs;
+        self.guest_fp_regs = regs.fp_simd_regs;
+        if let Some(gic_regs) = &mut self.guest_gic_regs {
+            gic_regs.copy_state_from(&regs.gic_regs);
+        }
+    }
+
+    /// Returns the saved GICv3 virtual CPU interface state of the guest, `None` if it is not
+    /// switched with the vCPU, see [`Aarch64VCpuSetupConfig::gicv3_context`].
+    ///
+    /// [`GicV3Registers::free_lrs`] and [`GicV3Registers::irq_state`] tell which list registers
+    /// are free and the state of the interrupts in flight.
+    pub fn gic_regs(&self) -> Option<&GicV3Registers> {
+        self.guest_gic_regs.as_ref()
+    }
+
+    /// Returns the saved GICv3 virtual CPU interface state of the guest for modification, e.g.
+    /// to fill list registers directly, `None` if it is not switched with the vCPU.
+    pub fn gic_regs_mut(&mut self) -> Option<&mut GicV3Registers> {
+        self.guest_gic_regs.as_mut()
+    }
+
+    /// Takes a snapshot of the architectural state of the vCPU, see [`VCpuSnapshot`].
+    ///
+    /// A pending advance of the guest PC past a trapped instruction is applied to the snapshot.
+    /// Returns [`AxError::BadState`](axerrno::AxError::BadState) if an MMIO read has not been
+    /// completed with [`Self::complete_mmio_read`], or if an MMIO load/store emulated from its
+    /// instruction is in progress, as they can not be resumed from a snapshot. Snapshots do not
+    /// cover the SVE registers, so vCPUs with SVE enabled are refused with `BadState` as well.
+    pub fn snapshot(&self) -> AxResult<VCpuSnapshot> {
+        if self.sve_vl != 0 {
+            return ax_err!(BadState, "SVE state is not covered by snapshots");
+        }
+        if self.pending_mmio_read.is_some() {
+            return ax_err!(BadState, "MMIO read not completed");
+        }
+        if self.mmio_emulation.is_some() {
+            return ax_err!(BadState, "MMIO emulation in progress");
+        }
+
+        let (pc, pstate) = self.resume_pc_pstate();
+
+        let mut sys_regs = [0; VCpuSnapshot::SYS_REGS.len()];
+        for (value, reg) in sys_regs.iter_mut().zip(VCpuSnapshot::SYS_REGS) {
+            *value = self.guest_system_regs.reg(reg).unwrap_or_default();
+        }
+        let regs = &self.guest_system_regs;
+        let (cntp_ctl_el0, cntp_cval_el0) = if self.passthrough_timer {
+            (regs.cntp_ctl_el0 as u64, regs.cntp_cval_el0)
+        } else {
+            self.ptimer.state()
+        };
+
+        Ok(VCpuSnapshot {
+            mpidr: self.mpidr,
+            aarch32: self.aarch32,
+            gpr: self.ctx.gpr,
+            sp_el0: self.ctx.sp_el0,
+            pc,
+            pstate,
+            sys_regs,
+            cntkctl_el1: regs.cntkctl_el1 as u64,
+            cntv_ctl_el0: regs.cntv_ctl_el0 as u64,
+            cntv_cval_el0: regs.cntv_cval_el0,
+            cntp_ctl_el0,
+            cntp_cval_el0,
+            cntvoff_el2: regs.cntvoff_el2,
+            host_counter: CNTPCT_EL0.get(),
+            vregs: self.guest_fp_regs.vregs,
+            fpsr: self.guest_fp_regs.fpsr,
+            fpcr: self.guest_fp_regs.fpcr,
+        })
+    }
+
+    /// Restores the architectural state of the vCPU from `snapshot`, taking effect when the guest
+    /// is resumed.
+    ///
+    /// This is meant to be called after `setup`, which sets up the EL2 controls of the vCPU.
+    /// `CNTVOFF_EL2` is restored as is, see [`VCpuSnapshot::counter_offset_for_host`] to adjust
+    /// it for another host. Returns [`AxError::InvalidInput`](axerrno::AxError::InvalidInput)
+    /// if the execution state of EL1 of the snapshot does not match the vCPU or one of its system
+    /// registers can not be restored, and
+    /// [`AxError::BadState`](axerrno::AxError::BadState) if the vCPU has SVE enabled, as
+    /// snapshots do not cover the SVE registers.
+    pub fn restore(&mut self, snapshot: &VCpuSnapshot) -> AxResult {
+        if self.sve_vl != 0 {
+            return ax_err!(BadState, "SVE state is not covered by snapshots");
+        }
+        if snapshot.aarch32 != self.aarch32 {
+            return ax_err!(InvalidInput, "execution state of the snapshot mismatched");
+        }
+
+        // The system registers are restored into a copy first, so that the vCPU is left untouched
+        // on failure.
+        let mut regs = self.guest_system_regs;
+        for (reg, value) in VCpuSnapshot::SYS_REGS.into_iter().zip(snapshot.sys_regs) {
+            if !regs.set_reg(reg, value) {
+                return ax_err!(InvalidInput, "unsupported register in the snapshot");
+            }
+        }
+        regs.vmpidr_el2 = (1 << 31) | snapshot.mpidr;
+        regs.cntkctl_el1 = snapshot.cntkctl_el1 as u32;
+        regs.cntv_ctl_el0 = snapshot.cntv_ctl_el0 as u32;
+        regs.cntv_cval_el0 = snapshot.cntv_cval_el0;
+        if self.passthrough_timer {
+            regs.cntp_ctl_el0 = snapshot.cntp_ctl_el0 as u32;
+            regs.cntp_cval_el0 = snapshot.cntp_cval_el0;
+        }
+        regs.cntvoff_el2 = snapshot.cntvoff_el2;
+        regs.cntvct_el0 = CNTPCT_EL0.get().wrapping_sub(snapshot.cntvoff_el2);
+
+        self.retry_instruction();
+        self.mpidr = snapshot.mpidr;
+        self.guest_system_regs = regs;
+        self.ctx.gpr = snapshot.gpr;
+        self.ctx.sp_el0 = snapshot.sp_el0;
+        self.ctx.elr = snapshot.pc;
+        self.ctx.spsr = snapshot.pstate;
+        self.guest_fp_regs.vregs = snapshot.vregs;
+        self.guest_fp_regs.fpsr = snapshot.fpsr;
+        self.guest_fp_regs.fpcr = snapshot.fpcr;
+        if !self.passthrough_timer {
+            self.ptimer
+                .set_state(snapshot.cntp_ctl_el0, snapshot.cntp_cval_el0);
+        }
+        Ok(())
+    }
+
+    /// Returns the offset of the guest counter, `CNTVOFF_EL2`.
+    ///
+    /// The guest virtual counter, and the guest physical counter if timers are not passed
+    /// through, read as the host counter minus this offset.
+    pub fn counter_offset(&self) -> u64 {
+        self.guest_system_regs.cntvoff_el2
+    }
+
+    /// Sets the offset of the guest counter, `CNTVOFF_EL2`, see [`Self::counter_offset`].
+    ///
+    /// All vCPUs of a VM should share the same offset. To stop guest time from advancing while
+    /// the VM is paused, add the number of host counter ticks the VM has been paused for to the
+    /// offset before resuming it.
+    pub fn set_counter_offset(&mut self, offset: u64) {
+        self.guest_system_regs.cntvoff_el2 = offset;
+    }
+
+    /// Returns the earliest deadline of the enabled and unmasked timers of the guest, in ticks of
+    /// the host counter (`CNTPCT_EL0`), `None` if no timer is armed.
+    ///
+    /// While the vCPU is not running, its timers can not fire by themselves, so the VMM should
+    /// arm a host timer at this deadline, and inject the timer PPI of the guest when it expires.
+    pub fn timer_next_deadline(&self) -> Option<u64> {
+        let cntvoff = self.guest_system_regs.cntvoff_el2;
+        let vtimer = timer_deadline(
+            self.guest_system_regs.cntv_ctl_el0 as u64,
+            self.guest_system_regs.cntv_cval_el0,
+        )
+        .map(|cval| cval.wrapping_add(cntvoff));
+        // The passed through physical timer compares against the host counter, while the emulated
+        // one compares against the guest counter.
+        let ptimer = if self.passthrough_timer {
+            timer_deadline(
+                self.guest_system_regs.cntp_ctl_el0 as u64,
+                self.guest_system_regs.cntp_cval_el0,
+            )
+        } else {
+            let (ctl, cval) = self.ptimer.state();
+            timer_deadline(ctl, cval).map(|cval| cval.wrapping_add(cntvoff))
+        };
+
+        [vtimer, ptimer].into_iter().flatten().min()
+    }
+
+    /// Returns whether the interrupt of the virtual timer of the guest was pending, i.e.,
+    /// the timer was enabled and unmasked with its condition met, at the last VM exit.
+    pub fn vtimer_irq_pending(&self) -> bool {
+        timer_irq_asserted(self.guest_system_regs.cntv_ctl_el0 as u64)
+    }
+
+    /// Returns whether the interrupt of the EL1 physical timer of the guest was pending, i.e.,
+    /// the timer was enabled and unmasked with its condition met, at the last VM exit.
+    pub fn ptimer_irq_pending(&self) -> bool {
+        if self.passthrough_timer {
+            timer_irq_asserted(self.guest_system_regs.cntp_ctl_el0 as u64)
+        } else {
+            // The emulated timer compares against the guest counter, saved at the last VM exit.
+            let (ctl, cval) = self.ptimer.state();
+            timer_deadline(ctl, cval).is_some()
+                && timer_condition_met(ctl, cval, self.guest_system_regs.cntvct_el0)
+        }
+    }
+
+    /// Completes the MMIO read reported by the last VM exit with `value`, writing it to the
+    /// destination register of the guest.
+    ///
+    /// `value` is truncated to the width of the access, byte-swapped if the guest accesses data in
+    /// big-endian (see [`Self::data_endianness`]), then sign-extended (for `LDRSB`, `LDRSH` and
+    /// `LDRSW`) or zero-extended to the width of the destination register. Writes to `Wn`
+    /// clear the upper 32 bits of `Xn`, and writes to `XZR` are discarded.
+    ///
+    /// Loads of SIMD&FP registers, or of more than one register, are emulated by the vCPU when the
+    /// data abort has no valid instruction syndrome, and reported as one MMIO read per register
+    /// by successive calls of `run`. These reads have to be completed with this method, reads of
+    /// SIMD&FP registers being reported with `reg` 31, i.e., `XZR`.
+    ///
+    /// Returns [`AxError::BadState`](axerrno::AxError::BadState) if no MMIO read is pending.
+    pub fn complete_mmio_read(&mut self, value: u64) -> AxResult {
+        let Some(read) = self.pending_mmio_read.take() else {
+            return ax_err!(BadState, "no MMIO read to complete");
+        };
+
+        let bits = read.width.size() * 8;
+        let value = if bits < 64 {
+            value & ((1 << bits) - 1)
+        } else {
+            value
+        };
+        let mut value = self.mmio_data_swap(value, read.width);
+        if read.sign_ext && bits < 64 {
+            let shift = 64 - bits;
+            value = (((value << shift) as i64) >> shift) as u64;
+        }
+        if let Some(offset) = read.fp_offset {
+            let vreg = &mut self.guest_fp_regs.vregs[read.reg];
+            if read.fp_half {
+                *vreg &= !((u64::MAX as u128) << (offset * 8));
+                *vreg |= (value as u128) << (offset * 8);
+            } else {
+                // Loads of SIMD&FP registers clear the bits above the loaded ones.
+                *vreg = value as u128;
+            }
+            return Ok(());
+        }
+        if read.reg_width.size() == 4 {
+            value &= 0xffff_ffff;
+        }
+
+        if read.reg != 31 {
+            self.ctx.set_gpr(read.reg, value as usize);
+        }
+        Ok(())
+    }
+
+    /// Makes the guest execute the last trapped instruction again when it is resumed, instead of
+    /// skipping it.
+    ///
+    /// MMIO and system register accesses reported to the VMM are only completed by advancing
+    /// the guest PC when the guest is resumed by the next `run`. The VMM can call this instead
+    /// to retry the access, e.g. after mapping the page an MMIO access faulted on. Injecting a
+    /// synchronous exception has the same effect, as the guest takes it at the instruction.
+    pub fn retry_instruction(&mut self) {
+        self.pending_pc_step = 0;
+        self.pending_mmio_read = None;
+        self.mmio_emulation = None;
+    }
+
+    /// Reports the next access of the MMIO load/store being emulated, see [`MmioEmulation`].
+    ///
+    /// Once all the accesses are reported, the base register is written back and the guest PC
+    /// is advanced past the instruction, and `None` is returned so that the guest is resumed.
+    pub(crate) fn step_mmio_emulation(&mut self) -> Option<AxVCpuExitReason> {
+        let mut emulation = self.mmio_emulation.take()?;
+        if emulation.next == emulation.count() {
+            if let Some(value) = emulation.writeback {
+                self.set_guest_base_register(emulation.access.rn, value);
+            }
+            // A64 instructions are always 4 bytes.
+            self.pending_pc_step = 4;
+            return None;
+        }
+
+        let i = emulation.next;
+        emulation.next += 1;
+        self.mmio_emulation = Some(emulation);
+
+        let access = emulation.access;
+        let (reg, mut offset) = emulation.element(i);
+        // The first half of a big-endian 128-bit register in memory is its upper half.
+        if access.size == 16 && self.data_endianness() == Endianness::Big {
+            offset = 8 - offset;
+        }
+        let addr = emulation.addrs[i];
+        let width = AccessWidth::try_from(emulation.width()).ok()?;
+        self.exit_detail = Aarch64ExitDetail::Mmio {
+            acquire_release: false,
+        };
+        self.pending_mmio_read = None;
+
+        if !access.load {
+            let data = if access.simd {
+                // Make sure the guest SIMD&FP registers are saved in `guest_fp_regs`.
+                unsafe { self.put_guest_fp_regs() };
+                (self.guest_fp_regs.vregs[reg] >> (offset * 8)) as u64
+            } else {
+                self.ctx.gpr(reg) as u64
+            };
+            let data = match width.size() {
+                8 => data,
+                size => data & ((1 << (size * 8)) - 1),
+            };
+            let data = self.mmio_data_swap(data, width);
+            return Some(AxVCpuExitReason::MmioWrite { addr, width, data });
+        }
+
+        let reg_width = if access.simd || access.reg_width == 8 {
+            AccessWidth::Qword
+        } else {
+            AccessWidth::Dword
+        };
+        if access.simd {
+            unsafe { self.put_guest_fp_regs() };
+        }
+        self.pending_mmio_read = Some(PendingMmioRead {
+            reg,
+            width,
+            reg_width,
+            sign_ext: access.sign_ext,
+            fp_offset: access.simd.then_some(offset),
+            fp_half: access.size == 16,
+        });
+        Some(AxVCpuExitReason::MmioRead {
+            addr,
+            width,
+            reg: if access.simd { 31 } else { reg },
+            reg_width,
+            signed_ext: access.sign_ext,
+        })
+    }
+
+    /// Returns the value of the base register `rn` of a guest load/store, 31 being the stack
+    /// pointer of the current guest exception level.
+    pub(crate) fn guest_base_register(&self, rn: usize) -> u64 {
+        match rn {
+            31 if self.ctx.spsr & 0b1111 == SPSR_EL1::M::EL1h.value => {
+                self.guest_system_regs.sp_el1
+            }
+            31 => self.ctx.sp_el0,
+            _ => self.ctx.gpr(rn) as u64,
+        }
+    }
+
+    /// Sets the base register `rn` of a guest load/store, see [`Self::guest_base_register`].
+    fn set_guest_base_register(&mut self, rn: usize, value: u64) {
+        match rn {
+            31 if self.ctx.spsr & 0b1111 == SPSR_EL1::M::EL1h.value => {
+                self.guest_system_regs.sp_el1 = value;
+            }
+            31 => self.ctx.sp_el0 = value,
+            _ => self.ctx.set_gpr(rn, value as usize),
+        }
+    }
+
+    /// Returns the PC and PSTATE the guest resumes with, i.e. with the pending advance past the
+    /// last trapped instruction applied.
+    fn resume_pc_pstate(&self) -> (u64, u64) {
+        let (mut pc, mut pstate) = (self.ctx.elr, self.ctx.spsr);
+        if self.pending_pc_step != 0 {
+            pc += self.pending_pc_step as u64;
+            if self.ctx.is_aarch32() {
+                pstate = aarch32_it_advance(pstate);
+            }
+        }
+        (pc, pstate)
+    }
+
+    /// Advances the guest PC past the last trapped instruction, if it has not been done yet.
+    ///
+    /// For guests in AArch32 T32 state, the IT state is advanced as well.
+    fn commit_pc_advance(&mut self) {
+        if self.pending_pc_step == 0 {
+            return;
+        }
+
+        let pc = self.ctx.exception_pc() + self.pending_pc_step;
+        self.ctx.set_exception_pc(pc);
+        if self.ctx.is_aarch32() {
+            self.ctx.spsr = aarch32_it_advance(self.ctx.spsr);
+        }
+        self.pending_pc_step = 0;
+    }
+
+    /// Injects a virtual SError into the guest.
+    ///
+    /// The SError stays pending until the guest unmasks SErrors with `PSTATE.A`. If the CPU
+    /// implements RAS, the guest sees `syndrome` in `ESR_EL1.ISS` (for an AArch64 guest) or in
+    /// `DFSR.{AET, ExT}` (for an AArch32 guest), otherwise the syndrome is IMPLEMENTATION DEFINED.
+    pub fn inject_serror(&mut self, syndrome: u64) {
+        const VSESR_EL2_MASK: u64 = 0x1ff_ffff;
+
+        self.guest_system_regs.vsesr_el2 = syndrome & VSESR_EL2_MASK;
+        self.guest_system_regs.hcr_el2 |= HCR_EL2_VSE;
+    }
+
+    /// Injects a synchronous external data abort on `addr` into the guest.
+    ///
+    /// The guest takes the abort at its current PC, with `addr` as the faulting virtual address
+    /// reported in `FAR_EL1` (or `DFAR` for an AArch32 guest).
+    pub fn inject_data_abort(&mut self, addr: usize) {
+        if self.aarch32 {
+            let fsr = self.aarch32_external_abort_fsr();
+            self.guest_system_regs.esr_el1 = fsr;
+            self.guest_system_regs.far_el1 =
+                (self.guest_system_regs.far_el1 & !0xffff_ffff) | (addr as u32 as u64);
+            self.inject_exception32(SPSR_AARCH32_MODE_ABT, AARCH32_VECTOR_DABT, (8, 8));
+        } else {
+            let esr = self.aarch64_abort_esr(ESR_EC_DABT_LOW, ESR_EC_DABT_CUR);
+            self.guest_system_regs.far_el1 = addr as u64;
+            self.inject_exception64(esr);
+        }
+    }
+
+    /// Injects a synchronous external prefetch abort on `addr` into the guest.
+    ///
+    /// The guest takes the abort at its current PC, with `addr` as the faulting virtual address
+    /// reported in `FAR_EL1` (or `IFAR` for an AArch32 guest).
+    pub fn inject_prefetch_abort(&mut self, addr: usize) {
+        if self.aarch32 {
+            let fsr = self.aarch32_external_abort_fsr();
+            self.guest_system_regs.ifsr32_el2 = fsr;
+            self.guest_system_regs.far_el1 =
+                (self.guest_system_regs.far_el1 & 0xffff_ffff) | ((addr as u32 as u64) << 32);
+            self.inject_exception32(SPSR_AARCH32_MODE_ABT, AARCH32_VECTOR_PABT, (4, 4));
+        } else {
+            let esr = self.aarch64_abort_esr(ESR_EC_IABT_LOW, ESR_EC_IABT_CUR);
+            self.guest_system_regs.far_el1 = addr as u64;
+            self.inject_exception64(esr);
+        }
+    }
+
+    /// Injects an undefined instruction exception into the guest, as if the instruction at its
+    /// current PC was undefined.
+    pub fn inject_undefined(&mut self) {
+        if self.aarch32 {
+            self.inject_exception32(SPSR_AARCH32_MODE_UND, AARCH32_VECTOR_UND, (4, 2));
+        } else {
+            self.inject_exception64(ESR_ELX_IL);
+        }
+    }
+
+    /// Returns the `ESR_EL1` value of a synchronous external abort, for AArch64 guests.
+    ///
+    /// `ec_lower` and `ec_current` are the exception classes of the abort taken from EL0 and
+    /// from EL1 respectively.
+    fn aarch64_abort_esr(&self, ec_lower: u64, ec_current: u64) -> u64 {
+        const ESR_ELX_FSC_EXTABT: u64 = 0b01_0000;
+
+        let from_el0 = self.ctx.spsr & SPSR_AARCH32_STATE != 0 || self.ctx.spsr & SPSR_EL_MASK == 0;
+        let ec = if from_el0 { ec_lower } else { ec_current };
+        (ec << ESR_ELX_EC_SHIFT) | ESR_ELX_IL | ESR_ELX_FSC_EXTABT
+    }
+
+    /// Returns the `DFSR`/`IFSR` value of a synchronous external abort, for AArch32 guests.
+    ///
+    /// The format depends on whether the guest uses the long-descriptor translation table
+    /// format, according to `TTBCR.EAE`.
+    fn aarch32_external_abort_fsr(&self) -> u32 {
+        const TTBCR_EAE: u64 = 1 << 31;
+        const FSR_LPAE: u32 = 1 << 9;
+        const FSR_FSC_EXTABT_LPAE: u32 = 0b01_0000;
+        const FSR_FS_EXTABT_NLPAE: u32 = 0b0_1000;
+
+        if self.guest_system_regs.tcr_el1 & TTBCR_EAE != 0 {
+            FSR_LPAE | FSR_FSC_EXTABT_LPAE
+        } else {
+            FSR_FS_EXTABT_NLPAE
+        }
+    }
+
+    /// Makes the guest take a synchronous exception to EL1 in AArch64 state, reporting `esr`.
+    ///
+    /// Follows the `TakeException()` pseudocode: the current PC and PSTATE are saved to `ELR_EL1`
+    /// and `SPSR_EL1`, and the guest resumes at the synchronous exception vector matching the
+    /// state it was in, in EL1h with DAIF masked.
+    ///
+    /// The exception is taken at the trapped instruction, so a pending advance of the guest PC
+    /// is cancelled.
+    fn inject_exception64(&mut self, esr: u64) {
+        self.pending_pc_step = 0;
+        self.mmio_emulation = None;
+        const VECTOR_CURRENT_SP0: u64 = 0x0;
+        const VECTOR_CURRENT_SPX: u64 = 0x200;
+        const VECTOR_LOWER_AARCH64: u64 = 0x400;
+        const VECTOR_LOWER_AARCH32: u64 = 0x600;
+        /// `SPSR.M[3:0]` for EL1 with `SP_EL0`.
+        const SPSR_EL1T: u64 = 0b0100;
+        const SPSR_DAIF: u64 = 0b1111 << 6;
+        const SPSR_PAN: u64 = 1 << 22;
+        const SPSR_DIT: u64 = 1 << 24;
+        const SCTLR_SPAN: u64 = 1 << 23;
+
+        let spsr = self.ctx.spsr;
+        let vector = if spsr & SPSR_AARCH32_STATE != 0 {
+            VECTOR_LOWER_AARCH32
+        } else {
+            match spsr & SPSR_EL_MASK {
+                0 => VECTOR_LOWER_AARCH64,
+                SPSR_EL1T => VECTOR_CURRENT_SP0,
+                _ => VECTOR_CURRENT_SPX,
+            }
+        };
+
+        let mut new_spsr = SPSR_EL1::M::EL1h.value | SPSR_DAIF | (spsr & (SPSR_PAN | SPSR_DIT));
+        if self.guest_system_regs.sctlr_el1 as u64 & SCTLR_SPAN == 0 {
+            new_spsr |= SPSR_PAN;
+        }
+
+        self.guest_system_regs.elr_el1 = self.ctx.exception_pc() as u64;
+        self.guest_system_regs.spsr_el1 = spsr as u32;
+        self.guest_system_regs.esr_el1 = esr as u32;
+        self.ctx.spsr = new_spsr;
+        self.ctx
+            .set_exception_pc((self.guest_system_regs.vbar_el1 + vector) as usize);
+    }
+
+    /// Makes the guest take an exception to `mode` in AArch32 state.
+    ///
+    /// Follows the `AArch32.EnterMode()` pseudocode: the current CPSR is saved to the SPSR of
+    /// `mode`, the return address to its banked LR, and the guest resumes at `vector_offset` of
+    /// the vector table. `return_offset` is the offset of the return address from the current
+    /// PC, in A32 and T32 state respectively.
+    ///
+    /// As for AArch64 guests, a pending advance of the guest PC is cancelled.
+    fn inject_exception32(&mut self, mode: u64, vector_offset: u64, return_offset: (u64, u64)) {
+        self.pending_pc_step = 0;
+        self.mmio_emulation = None;
+        /// `LR_abt` and `LR_und`, mapped to `X20` and `X22`.
+        const GPR_LR_ABT: usize = 20;
+        const GPR_LR_UND: usize = 22;
+        const SPSR_F: u64 = 1 << 6;
+        const SPSR_I: u64 = 1 << 7;
+        const SPSR_A: u64 = 1 << 8;
+        const SPSR_E: u64 = 1 << 9;
+        const SPSR_DIT: u64 = 1 << 21;
+        const SPSR_PAN: u64 = 1 << 22;
+        const SCTLR_V: u64 = 1 << 13;
+        const SCTLR_SPAN: u64 = 1 << 23;
+        const SCTLR_EE: u64 = 1 << 25;
+        const SCTLR_TE: u64 = 1 << 30;
+        /// Vector base with `SCTLR.V` set (Hivecs).
+        const HIGH_VECTORS: u64 = 0xffff_0000;
+
+        let cpsr = self.ctx.spsr;
+        let sctlr = self.guest_system_regs.sctlr_el1 as u64;
+
+        let mut new_cpsr = mode | SPSR_A | SPSR_I | (cpsr & (SPSR_F | SPSR_DIT));
+        if sctlr & SCTLR_TE != 0 {
+            new_cpsr |= SPSR_AARCH32_T;
+        }
+        if sctlr & SCTLR_EE != 0 {
+            new_cpsr |= SPSR_E;
+        }
+        if sctlr & SCTLR_SPAN == 0 {
+            new_cpsr |= SPSR_PAN;
+        }
+
+        let return_address = self.ctx.exception_pc() as u64
+            + if cpsr & SPSR_AARCH32_T != 0 {
+                return_offset.1
+            } else {
+                return_offset.0
+            };
+        let vector_base = if sctlr & SCTLR_V != 0 {
+            HIGH_VECTORS
+        } else {
+            self.guest_system_regs.vbar_el1 & 0xffff_ffff
+        };
+
+        if mode == SPSR_AARCH32_MODE_ABT {
+            self.guest_system_regs.spsr_abt = cpsr as u32;
+            self.ctx.gpr[GPR_LR_ABT] = return_address as u32 as u64;
+        } else {
+            self.guest_system_regs.spsr_und = cpsr as u32;
+            self.ctx.gpr[GPR_LR_UND] = return_address as u32 as u64;
+        }
+        self.ctx.spsr = new_cpsr;
+        self.ctx
+            .set_exception_pc((vector_base + vector_offset) as usize);
+    }
+
+    /// Returns the byte order of the data accesses of the guest at its current exception level.
+    ///
+    /// For AArch64 guests, this is `SCTLR_EL1.EE` at EL1 and `SCTLR_EL1.E0E` at EL0, and for
+    /// AArch32 guests `PSTATE.E`, as set by `SETEND`. MMIO exits and
+    /// [`Self::complete_mmio_read`] byte-swap the data of big-endian accesses, so that the VMM
+    /// always sees the data in the byte order of the bus.
+    pub fn data_endianness(&self) -> Endianness {
+        /// `SCTLR_EL1.E0E`, the endianness of data accesses at EL0.
+        const SCTLR_E0E: u64 = 1 << 24;
+        /// `SCTLR_EL1.EE`, the endianness of data accesses at EL1.
+        const SCTLR_EE: u64 = 1 << 25;
+        /// `SPSR.E` of AArch32 states.
+        const SPSR_E: u64 = 1 << 9;
+
+        let big = if self.ctx.is_aarch32() {
+            self.ctx.spsr & SPSR_E != 0
+        } else {
+            let sctlr = self.guest_system_regs.sctlr_el1 as u64;
+            let bit = if self.ctx.spsr & SPSR_EL_MASK == 0 {
+                SCTLR_E0E
+            } else {
+                SCTLR_EE
+            };
+            sctlr & bit != 0
+        };
+        if big {
+            Endianness::Big
+        } else {
+            Endianness::Little
+        }
+    }
+
+    /// Converts `data` of an MMIO access of `width` between the byte order of the guest and
+    /// the one of the bus, which is the same conversion both ways.
+    pub(crate) fn mmio_data_swap(&self, data: u64, width: AccessWidth) -> u64 {
+        match (self.data_endianness(), width.size()) {
+            (Endianness::Big, size) if size > 1 => data.swap_bytes() >> (64 - size * 8),
+            _ => data,
+        }
+    }
+
+    /// Returns the AArch64 specific details of the last VM exit, see [`Aarch64ExitDetail`].
+    pub fn exit_detail(&self) -> Aarch64ExitDetail {
+        self.exit_detail
+    }
+
+    /// Switches the FP/SIMD registers of the current CPU from the host to the guest.
+    ///
+    /// The guest always enters with `CPTR_EL2.TFP` (and `CPTR_EL2.TZ`) set unless its FP/SIMD
+    /// registers are loaded, so this is called on the first FP/SIMD or SVE access of the guest
+    /// in each `run`. The host FP/SIMD registers are saved into the current percpu region.
+    ///
+    /// If SVE is enabled for the guest, the SVE registers are loaded as well, and `ZCR_EL2` is
+    /// set to limit the vector length of the guest. The SVE registers of the host beyond the
+    /// FP/SIMD registers are not preserved, as the host is not expected to use SVE.
+    pub(crate) unsafe fn load_guest_fp_regs(&mut self) {
+        if self.fp_loaded {
+            return;
+        }
+
+        unsafe {
+            CPTR_EL2.set(CPTR_EL2.get() & !(CPTR_EL2_TFP | CPTR_EL2_TZ));
+            barrier::isb(barrier::SY);
+
+            HOST_FP_REGS.current_ref_mut_raw().store();
+            self.guest_fp_regs.restore();
+            if self.aarch32 {
+                core::arch::asm!("msr FPEXC32_EL2, {0:x}", in(reg) self.guest_system_regs.fpexc32_el2);
+            }
+
+            if self.sve_vl != 0 {
+                HOST_ZCR_EL2.write_current_raw(read_zcr_el2());
+                write_zcr_el2((self.sve_vl / 16 - 1) as u64);
+                barrier::isb(barrier::SY);
+
+                // The `V` registers are restored again as the low bits of the `Z` registers, so
+                // the saved `V` registers are the ones that count.
+                self.guest_sve_regs
+                    .merge_vregs(&self.guest_fp_regs.vregs, self.sve_vl);
+                self.guest_sve_regs.restore();
+            }
+        }
+        self.fp_loaded = true;
+    }
+
+    /// Saves the guest FP/SIMD (and SVE) registers and gives the FP/SIMD unit back to the host,
+    /// if the guest FP/SIMD registers are loaded.
+    unsafe fn put_guest_fp_regs(&mut self) {
+        if !self.fp_loaded {
+            return;
+        }
+
+        unsafe {
+            CPTR_EL2.set(CPTR_EL2.get() & !(CPTR_EL2_TFP | CPTR_EL2_TZ));
+            barrier::isb(barrier::SY);
+
+            self.guest_fp_regs.store();
+            if self.aarch32 {
+                core::arch::asm!("mrs {0:x}, FPEXC32_EL2", out(reg) self.guest_system_regs.fpexc32_el2);
+            }
+            if self.sve_vl != 0 {
+                self.guest_sve_regs.store();
+                write_zcr_el2(HOST_ZCR_EL2.read_current_raw());
+                barrier::isb(barrier::SY);
+            }
+
+            HOST_FP_REGS.current_ref_raw().restore();
+        }
+        self.fp_loaded = false;
     }
 }
 
@@ -268,13 +1391,21 @@ impl<H: AxVCpuHal> Aarch64VCpu<H> {
     /// Restores guest system control registers.
     unsafe fn restore_vm_system_regs(&mut self) {
         unsafe {
+            // FP/SIMD accesses are trapped until the guest FP/SIMD registers get loaded,
+            // and so are SVE accesses if SVE is enabled for the guest.
+            let mut cptr_el2 = self.guest_system_regs.cptr_el2;
+            if !self.fp_loaded {
+                cptr_el2 |= CPTR_EL2_TFP;
+            } else if self.sve_vl != 0 {
+                cptr_el2 &= !CPTR_EL2_TZ;
+            }
+            CPTR_EL2.set(cptr_el2);
+
             // load system regs
-            core::arch::asm!(
-                "
-                mov x3, xzr           // Trap nothing from EL1 to El2.
-                msr cptr_el2, x3"
-            );
             self.guest_system_regs.restore();
+            if let Some(gic_regs) = &self.guest_gic_regs {
+                gic_regs.restore();
+            }
             core::arch::asm!(
                 "
                 ic  iallu
@@ -289,22 +1420,28 @@ impl<H: AxVCpuHal> Aarch64VCpu<H> {
     /// Handle VM-Exits.
     ///
     /// Parameters:
-    /// - `exit_reason`: The reason why the VM-Exit happened in [`TrapKind`].
+    /// - `exit_reason`: The reason why the VM-Exit happened, as a raw [`TrapKind`].
     ///
     /// Returns:
     /// - [`AxVCpuExitReason`]: a wrappered VM-Exit reason needed to be handled by the hypervisor.
     ///
-    /// This function may panic for unhandled exceptions.
-    fn vmexit_handler(&mut self, exit_reason: TrapKind) -> AxResult<AxVCpuExitReason> {
+    /// Unhandled exceptions are reported as [`AxVCpuExitReason::FailEntry`].
+    fn vmexit_handler(&mut self, exit_reason: usize) -> AxResult<AxVCpuExitReason> {
         trace!(
             "Aarch64VCpu vmexit_handler() esr:{:#x} ctx:{:#x?}",
             exception_class_value(),
             self.ctx
         );
 
+        self.exit_detail = Aarch64ExitDetail::None;
+        self.pending_mmio_read = None;
+
         unsafe {
             // Store guest system regs
             self.guest_system_regs.store();
+            if let Some(gic_regs) = &mut self.guest_gic_regs {
+                gic_regs.store();
+            }
 
             // Store guest `SP_EL0` into the `Aarch64VCpu` struct,
             // which will be restored when the guest is resumed in `exception_return_el2`.
@@ -315,16 +1452,29 @@ impl<H: AxVCpuHal> Aarch64VCpu<H> {
             restore_host_sp_el0();
         }
 
+        let Ok(exit_reason) = TrapKind::try_from(exit_reason as u8) else {
+            return fail_entry(self, "Invalid trap kind");
+        };
+
         let result = match exit_reason {
-            TrapKind::Synchronous => handle_exception_sync(&mut self.ctx),
-            TrapKind::Irq => Ok(AxVCpuExitReason::ExternalInterrupt {
+            TrapKind::Synchronous => handle_exception_sync(self),
+            // FIQs are only routed to EL2 when they are virtualized, handle them as IRQs.
+            TrapKind::Irq | TrapKind::Fiq => Ok(AxVCpuExitReason::ExternalInterrupt {
                 vector: H::irq_fetch() as _,
             }),
-            _ => panic!("Unhandled exception {:?}", exit_reason),
+            TrapKind::SError => handle_serror(self),
         };
 
         match result {
             Ok(AxVCpuExitReason::SysRegRead { addr, reg }) => {
+                // Trapped reads of VM control registers are completed here, the VMM may still
+                // override the value.
+                if self.trap_policy.traps_vm_control_reads()
+                    && let Some(value) = self.guest_system_regs.vm_control_read(addr)
+                {
+                    self.set_gpr(reg, value as usize);
+                }
+
                 if let Some(exit_reason) =
                     self.builtin_sysreg_access_handler(addr, false, 0, reg)?
                 {
@@ -334,6 +1484,10 @@ impl<H: AxVCpuHal> Aarch64VCpu<H> {
                 result
             }
             Ok(AxVCpuExitReason::SysRegWrite { addr, value }) => {
+                if self.handle_vm_control_write(addr, value) {
+                    return Ok(AxVCpuExitReason::Nothing);
+                }
+
                 if let Some(exit_reason) =
                     self.builtin_sysreg_access_handler(addr, true, value, 0)?
                 {
@@ -346,6 +1500,77 @@ impl<H: AxVCpuHal> Aarch64VCpu<H> {
         }
     }
 
+    /// Returns the value of the ID register `addr` in the view of the guest, 0 if `addr` is not
+    /// an ID register.
+    pub(crate) fn id_reg(&self, addr: SysRegAddr) -> u64 {
+        self.id_regs.get(addr).unwrap_or_default()
+    }
+
+    /// Returns the value a trapped MRRC of the 64-bit CP15 register mapped to `addr` reads,
+    /// `None` if it is not emulated.
+    pub(crate) fn cp15_64_read(&self, addr: SysRegAddr) -> Option<u64> {
+        self.guest_system_regs
+            .vm_control_read(addr)
+            .or_else(|| self.ptimer.read(addr, self.guest_system_regs.cntvoff_el2))
+    }
+
+    /// Returns whether the guest has both its MMU and its data caches enabled.
+    fn guest_caches_enabled(&self) -> bool {
+        // SCTLR_EL1.M and SCTLR_EL1.C, at the same position in the AArch32 SCTLR.
+        const SCTLR_M_C: u32 = (1 << 0) | (1 << 2);
+        self.guest_system_regs.sctlr_el1 & SCTLR_M_C == SCTLR_M_C
+    }
+
+    /// Emulates a data cache maintenance by set/way, trapped by `HCR_EL2.TSW`.
+    ///
+    /// The whole guest memory is cleaned and invalidated on the first set/way operation, which
+    /// typically comes in a loop over all the sets and ways, and the guest caches are tracked
+    /// from then on, see [`Self::handle_vm_control_write`].
+    fn handle_set_way(&mut self) {
+        if !self.set_way.set_way() {
+            return;
+        }
+
+        debug!("vCPU uses set/way cache maintenance, flushing the guest memory");
+        flush_guest_memory(
+            self.guest_system_regs.vttbr_el2,
+            self.guest_system_regs.vtcr_el2,
+        );
+        self.guest_system_regs.hcr_el2 |= HCR_EL2_TVM;
+    }
+
+    /// Applies a trapped write to a VM control register to the saved guest state.
+    ///
+    /// Returns `true` if the write was only trapped to track the guest caches after set/way
+    /// operations, so it should not be reported to the VMM.
+    fn handle_vm_control_write(&mut self, addr: SysRegAddr, value: u64) -> bool {
+        let reported = self.trap_policy.traps_vm_control_writes();
+        if !reported && !self.set_way.is_tracking() {
+            return false;
+        }
+
+        let caches_were_enabled = self.guest_caches_enabled();
+        if !self.guest_system_regs.vm_control_write(addr, value) {
+            return false;
+        }
+
+        let was_tracking = self.set_way.is_tracking();
+        if self
+            .set_way
+            .caches_updated(caches_were_enabled, self.guest_caches_enabled())
+        {
+            flush_guest_memory(
+                self.guest_system_regs.vttbr_el2,
+                self.guest_system_regs.vtcr_el2,
+            );
+        }
+        if was_tracking && !self.set_way.is_tracking() && !reported {
+            self.guest_system_regs.hcr_el2 &= !HCR_EL2_TVM;
+        }
+
+        !reported
+    }
+
     /// Handle system register access that can and should be handled by the VCpu itself.
     ///
     /// Return `Ok(None)` if the system register access is not handled by the VCpu itself,
@@ -357,48 +1582,43 @@ impl<H: AxVCpuHal> Aarch64VCpu<H> {
         reg: usize,
     ) -> AxResult<Option<AxVCpuExitReason>> {
         const SYSREG_ICC_SGI1R_EL1: SysRegAddr = SysRegAddr::new(0x3A_3016); // ICC_SGI1R_EL1
+        const SYSREG_ICC_ASGI1R_EL1: SysRegAddr = SysRegAddr::new(0x3C_3016); // ICC_ASGI1R_EL1
+        const SYSREG_ICC_SGI0R_EL1: SysRegAddr = SysRegAddr::new(0x3E_3016); // ICC_SGI0R_EL1
 
-        match (addr, write) {
-            (SYSREG_ICC_SGI1R_EL1, true) => {
-                debug!("arm_vcpu ICC_SGI1R_EL1 write: {value:#x}");
+        // Accesses to the EL1 physical timer only trap if it is emulated.
+        let cntvoff = self.guest_system_regs.cntvoff_el2;
+        if write {
+            if self.ptimer.write(addr, value, cntvoff) {
+                return Ok(Some(AxVCpuExitReason::Nothing));
+            }
+        } else if let Some(value) = self.ptimer.read(addr, cntvoff) {
+            self.set_gpr(reg, value as usize);
+            return Ok(Some(AxVCpuExitReason::Nothing));
+        }
 
-                // TODO: support RangeSelector
+        if write && is_dc_set_way(addr) {
+            self.handle_set_way();
+            return Ok(Some(AxVCpuExitReason::Nothing));
+        }
 
-                let intid = (value >> 24) & 0b1111;
-                let irm = ((value >> 40) & 0b1) != 0;
+        if !write && let Some(value) = self.id_regs.get(addr) {
+            self.set_gpr(reg, value as usize);
+            return Ok(Some(AxVCpuExitReason::Nothing));
+        }
 
-                // IRM == 1 => send to all except self
-                if irm {
-                    debug!("arm_vcpu ICC_SGI1R_EL1 write: irm == 1, send to all except self");
+        match (addr, write) {
+            (SYSREG_ICC_SGI1R_EL1 | SYSREG_ICC_ASGI1R_EL1 | SYSREG_ICC_SGI0R_EL1, true) => {
+                debug!("arm_vcpu SGI register {addr:?} write: {value:#x}");
 
-                    return Ok(Some(AxVCpuExitReason::SendIPI {
-                        target_cpu: 0,
-                        target_cpu_aux: 0,
-                        send_to_all: true,
-                        send_to_self: false,
-                        vector: intid,
-                    }));
+                // As KVM does, SGIs for the other Security state from `ICC_ASGI1R_EL1` are taken
+                // as Group 0 SGIs, as the guest runs in a single Security state.
+                if addr != SYSREG_ICC_SGI1R_EL1 {
+                    self.exit_detail = Aarch64ExitDetail::Group0Sgi;
                 }
-
-                let aff3 = (value >> 48) & 0xff;
-                let aff2 = (value >> 32) & 0xff;
-                let aff1 = (value >> 16) & 0xff;
-                let target_list = value & 0xffff;
-
-                debug!(
-                    "arm_vcpu ICC_SGI1R_EL1 write: aff3:{aff3:#x} aff2:{aff2:#x} aff1:{aff1:#x} intid:{intid:#x} target_list:{target_list:#x}"
-                );
-
-                Ok(Some(AxVCpuExitReason::SendIPI {
-                    target_cpu: (aff3 << 24) | (aff2 << 16) | (aff1 << 8),
-                    target_cpu_aux: target_list,
-                    send_to_all: false,
-                    send_to_self: false,
-                    vector: intid,
-                }))
+                Ok(Some(decode_sgi_register(value)))
             }
-            (SYSREG_ICC_SGI1R_EL1, false) => {
-                // ICC_SGI1R_EL1 is WO, we take it as RAZ.
+            (SYSREG_ICC_SGI1R_EL1 | SYSREG_ICC_ASGI1R_EL1 | SYSREG_ICC_SGI0R_EL1, false) => {
+                // SGI registers are WO, we take them as RAZ.
                 self.set_gpr(reg, 0);
                 Ok(Some(AxVCpuExitReason::Nothing))
             }
@@ -441,6 +1661,54 @@ pub(crate) fn max_gpt_level(pa_bits: usize) -> usize {
     }
 }
 
+/// Reads `ZCR_EL2`, which requires SVE accesses not to be trapped at EL2.
+unsafe fn read_zcr_el2() -> u64 {
+    let zcr: u64;
+    unsafe { core::arch::asm!("mrs {0}, S3_4_C1_C2_0", out(reg) zcr) };
+    zcr
+}
+
+/// Writes `ZCR_EL2`, which requires SVE accesses not to be trapped at EL2.
+unsafe fn write_zcr_el2(zcr: u64) {
+    unsafe { core::arch::asm!("msr S3_4_C1_C2_0, {0}", in(reg) zcr) };
+}
+
+/// Returns whether EL1 of the current CPU supports AArch32 state.
+pub(crate) fn el1_aarch32_supported() -> bool {
+    // ID_AA64PFR0_EL1.EL1 == 0b0010: EL1 can be executed in either AArch64 or AArch32 state.
+    (ID_AA64PFR0_EL1.get() >> 4) & 0xf == 0b0010
+}
+
+/// Returns whether the current CPU implements SVE.
+pub(crate) fn sve_supported() -> bool {
+    ID_AA64PFR0_EL1.read(ID_AA64PFR0_EL1::SVE) != 0
+}
+
+/// Returns the SVE vector length in bytes that the current CPU provides to lower ELs when
+/// `vl` bytes are requested through `ZCR_EL2.LEN`.
+///
+/// The current CPU must implement SVE.
+pub(crate) fn sve_effective_vl(vl: usize) -> usize {
+    let cptr_el2 = CPTR_EL2.get();
+    let effective_vl: usize;
+
+    unsafe {
+        CPTR_EL2.set(cptr_el2 & !CPTR_EL2_TZ);
+        barrier::isb(barrier::SY);
+
+        let zcr_el2 = read_zcr_el2();
+        write_zcr_el2((vl / 16 - 1) as u64);
+        barrier::isb(barrier::SY);
+        core::arch::asm!(".arch_extension sve", "rdvl {0}, #1", out(reg) effective_vl);
+        write_zcr_el2(zcr_el2);
+
+        CPTR_EL2.set(cptr_el2);
+        barrier::isb(barrier::SY);
+    }
+
+    effective_vl
+}
+
 fn probe_vtcr_support() -> u64 {
     let pa_bits = pa_bits();
 